@@ -0,0 +1,95 @@
+//! Throughput of concurrent database work on a small runtime
+//!
+//! Compares running diesel calls directly on the async worker threads with running them
+//! through [`expenser::database::run`]. Needs `DATABASE_URL` pointing at a migrated database.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use diesel::{
+    r2d2::{ConnectionManager, Pool},
+    sql_query, PgConnection, RunQueryDsl,
+};
+use expenser::Report;
+
+const WORKER_THREADS: usize = 4;
+const CONCURRENCY: [usize; 3] = [8, 32, 128];
+/// Stands in for network and planning latency on a round trip to a remote database
+const ROUND_TRIP: &str = "SELECT pg_sleep(0.002)";
+
+fn query(conn: &mut PgConnection) {
+    sql_query(ROUND_TRIP)
+        .execute(conn)
+        .expect("Unable to run query");
+    Report::get_by_owner(1, conn).expect("Unable to get reports");
+}
+
+async fn inline(pool: Pool<ConnectionManager<PgConnection>>, tasks: usize) {
+    let handles: Vec<_> = (0..tasks)
+        .map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let conn = &mut expenser::database::get_connection(&pool)
+                    .expect("Unable to get connection");
+                query(conn);
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.await.expect("Task panicked");
+    }
+}
+
+async fn spawn_blocking(pool: Pool<ConnectionManager<PgConnection>>, tasks: usize) {
+    let handles: Vec<_> = (0..tasks)
+        .map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                expenser::database::run(&pool, query)
+                    .await
+                    .expect("Unable to run query");
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.await.expect("Task panicked");
+    }
+}
+
+fn concurrent_queries(c: &mut Criterion) {
+    dotenvy::dotenv().ok();
+    let pool = match expenser::database::PoolConfig::from_env()
+        .and_then(|config| expenser::database::pool(&config))
+    {
+        Ok(pool) => pool,
+        Err(_) => {
+            eprintln!("Skipping database benchmarks, DATABASE_URL is not set or reachable");
+            return;
+        }
+    };
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(WORKER_THREADS)
+        .enable_all()
+        .build()
+        .expect("Unable to build runtime");
+
+    let mut group = c.benchmark_group("concurrent_queries");
+    for tasks in CONCURRENCY {
+        group.throughput(Throughput::Elements(tasks as u64));
+        group.bench_with_input(BenchmarkId::new("inline", tasks), &tasks, |b, &tasks| {
+            b.to_async(&runtime).iter(|| inline(pool.clone(), tasks))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("spawn_blocking", tasks),
+            &tasks,
+            |b, &tasks| {
+                b.to_async(&runtime)
+                    .iter(|| spawn_blocking(pool.clone(), tasks))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_queries);
+criterion_main!(benches);
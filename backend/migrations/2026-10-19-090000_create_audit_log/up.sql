@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+    id bigint GENERATED ALWAYS AS IDENTITY,
+    actor_id bigint,
+    report_id bigint,
+    entity VARCHAR(64) NOT NULL,
+    entity_id bigint NOT NULL,
+    action VARCHAR(16) NOT NULL,
+    diff jsonb NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(id)
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_report_id ON audit_log(report_id);
+
+-- Entries are append-only; actor and report ids are kept without foreign keys
+-- so that history survives the deletion of the rows it describes.
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
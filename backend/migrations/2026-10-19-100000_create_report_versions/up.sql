@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS report_versions (
+    id bigint GENERATED ALWAYS AS IDENTITY,
+    report_id bigint NOT NULL,
+    version integer NOT NULL,
+    actor_id bigint,
+    reason VARCHAR(64) NOT NULL,
+    snapshot jsonb NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(id),
+    UNIQUE(report_id, version),
+    CONSTRAINT fk_report
+        FOREIGN KEY(report_id)
+            REFERENCES reports(id)
+            ON DELETE CASCADE
+);
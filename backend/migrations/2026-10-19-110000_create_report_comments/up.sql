@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS report_comments (
+    id bigint GENERATED ALWAYS AS IDENTITY,
+    report_id bigint NOT NULL,
+    line_item_id bigint,
+    author_id bigint NOT NULL,
+    body text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz,
+    PRIMARY KEY(id),
+    CONSTRAINT fk_report
+        FOREIGN KEY(report_id)
+            REFERENCES reports(id)
+            ON DELETE CASCADE,
+    CONSTRAINT fk_line_item
+        FOREIGN KEY(line_item_id)
+            REFERENCES report_line_items(id)
+            ON DELETE CASCADE,
+    CONSTRAINT fk_author
+        FOREIGN KEY(author_id)
+            REFERENCES users(id)
+            ON DELETE CASCADE
+);
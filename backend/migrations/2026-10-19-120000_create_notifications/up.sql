@@ -0,0 +1,35 @@
+CREATE TABLE IF NOT EXISTS notification_preferences (
+    user_id bigint NOT NULL,
+    email_enabled boolean NOT NULL DEFAULT true,
+    report_shared boolean NOT NULL DEFAULT true,
+    report_submitted boolean NOT NULL DEFAULT true,
+    report_approved boolean NOT NULL DEFAULT true,
+    report_rejected boolean NOT NULL DEFAULT true,
+    PRIMARY KEY(user_id),
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id)
+            ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS notification_outbox (
+    id bigint GENERATED ALWAYS AS IDENTITY,
+    user_id bigint NOT NULL,
+    event VARCHAR(32) NOT NULL,
+    recipient VARCHAR(128) NOT NULL,
+    subject text NOT NULL,
+    body text NOT NULL,
+    attempts integer NOT NULL DEFAULT 0,
+    last_error text,
+    locked_until timestamptz,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    sent_at timestamptz,
+    PRIMARY KEY(id),
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id)
+            ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS notification_outbox_pending
+    ON notification_outbox(id) WHERE sent_at IS NULL;
@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS notifications (
+    id bigint GENERATED ALWAYS AS IDENTITY,
+    user_id bigint NOT NULL,
+    event VARCHAR(32) NOT NULL,
+    report_id bigint,
+    actor_id bigint,
+    message text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    read_at timestamptz,
+    PRIMARY KEY(id),
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id)
+            ON DELETE CASCADE,
+    CONSTRAINT fk_report
+        FOREIGN KEY(report_id)
+            REFERENCES reports(id)
+            ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS notifications_unread
+    ON notifications(user_id) WHERE read_at IS NULL;
+
+ALTER TABLE notification_preferences
+    ADD COLUMN IF NOT EXISTS comment_added boolean NOT NULL DEFAULT true;
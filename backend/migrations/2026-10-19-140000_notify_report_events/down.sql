@@ -0,0 +1,2 @@
+DROP TRIGGER IF EXISTS notify_report_event ON audit_log;
+DROP FUNCTION IF EXISTS notify_report_event;
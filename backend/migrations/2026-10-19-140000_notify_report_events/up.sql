@@ -0,0 +1,21 @@
+-- Every mutation of a report or its children writes an audit_log entry in the
+-- same transaction, so publishing from here covers all of them and only fires
+-- once the change has been committed.
+CREATE OR REPLACE FUNCTION notify_report_event() RETURNS trigger AS $$
+BEGIN
+    IF NEW.report_id IS NOT NULL THEN
+        PERFORM pg_notify('report_events', json_build_object(
+            'report_id', NEW.report_id,
+            'entity', NEW.entity,
+            'entity_id', NEW.entity_id,
+            'action', NEW.action,
+            'actor_id', NEW.actor_id
+        )::text);
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_report_event
+    AFTER INSERT ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION notify_report_event();
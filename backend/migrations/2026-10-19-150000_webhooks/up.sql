@@ -0,0 +1,42 @@
+-- Only grantable from the database, there is no endpoint for changing it
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS is_admin boolean NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS webhooks (
+    id bigint GENERATED ALWAYS AS IDENTITY,
+    url text NOT NULL,
+    secret VARCHAR(255) NOT NULL,
+    -- Events to deliver, e.g. `report.update` or `report_line_item.*`; empty for every event
+    events text[] NOT NULL DEFAULT '{}',
+    active boolean NOT NULL DEFAULT true,
+    created_by bigint,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(id),
+    CONSTRAINT fk_created_by
+        FOREIGN KEY(created_by)
+            REFERENCES users(id)
+            ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id bigint GENERATED ALWAYS AS IDENTITY,
+    webhook_id bigint NOT NULL,
+    event VARCHAR(64) NOT NULL,
+    payload jsonb NOT NULL,
+    attempts integer NOT NULL DEFAULT 0,
+    response_status integer,
+    last_error text,
+    locked_until timestamptz,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    delivered_at timestamptz,
+    PRIMARY KEY(id),
+    CONSTRAINT fk_webhook
+        FOREIGN KEY(webhook_id)
+            REFERENCES webhooks(id)
+            ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_id
+    ON webhook_deliveries(webhook_id);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_pending
+    ON webhook_deliveries(id) WHERE delivered_at IS NULL;
@@ -0,0 +1,10 @@
+DROP TRIGGER IF EXISTS bump_row_version ON report_proof;
+DROP TRIGGER IF EXISTS bump_row_version ON report_access;
+DROP TRIGGER IF EXISTS bump_row_version ON report_line_items;
+DROP TRIGGER IF EXISTS bump_row_version ON reports;
+DROP FUNCTION IF EXISTS bump_row_version();
+
+ALTER TABLE report_proof DROP COLUMN IF EXISTS version;
+ALTER TABLE report_access DROP COLUMN IF EXISTS version;
+ALTER TABLE report_line_items DROP COLUMN IF EXISTS version;
+ALTER TABLE reports DROP COLUMN IF EXISTS version;
@@ -0,0 +1,26 @@
+ALTER TABLE reports ADD COLUMN IF NOT EXISTS version integer NOT NULL DEFAULT 1;
+ALTER TABLE report_line_items ADD COLUMN IF NOT EXISTS version integer NOT NULL DEFAULT 1;
+ALTER TABLE report_access ADD COLUMN IF NOT EXISTS version integer NOT NULL DEFAULT 1;
+ALTER TABLE report_proof ADD COLUMN IF NOT EXISTS version integer NOT NULL DEFAULT 1;
+
+-- Bumped by the database so that every update, including ones made outside the
+-- API, invalidates the versions clients hold.
+CREATE OR REPLACE FUNCTION bump_row_version() RETURNS trigger AS $$
+BEGIN
+    NEW.version := OLD.version + 1;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER bump_row_version
+    BEFORE UPDATE ON reports
+    FOR EACH ROW EXECUTE FUNCTION bump_row_version();
+CREATE TRIGGER bump_row_version
+    BEFORE UPDATE ON report_line_items
+    FOR EACH ROW EXECUTE FUNCTION bump_row_version();
+CREATE TRIGGER bump_row_version
+    BEFORE UPDATE ON report_access
+    FOR EACH ROW EXECUTE FUNCTION bump_row_version();
+CREATE TRIGGER bump_row_version
+    BEFORE UPDATE ON report_proof
+    FOR EACH ROW EXECUTE FUNCTION bump_row_version();
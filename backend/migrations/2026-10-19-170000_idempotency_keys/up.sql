@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key VARCHAR(255) NOT NULL,
+    actor_id bigint,
+    request_sha256 VARCHAR(64) NOT NULL,
+    -- Null while the first request with the key is still being handled
+    response_status integer,
+    response_headers jsonb,
+    response_body bytea,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(key)
+);
+
+CREATE INDEX IF NOT EXISTS idempotency_keys_created_at ON idempotency_keys(created_at);
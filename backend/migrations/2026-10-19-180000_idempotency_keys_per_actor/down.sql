@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS idempotency_keys_actor_key;
+ALTER TABLE idempotency_keys DROP COLUMN IF EXISTS id;
+-- Keys used by several actors cannot share a primary key, and are only a cache
+DELETE FROM idempotency_keys;
+ALTER TABLE idempotency_keys ADD PRIMARY KEY (key);
@@ -0,0 +1,6 @@
+-- Clients choose their own keys, so they only need to be unique per actor
+ALTER TABLE idempotency_keys DROP CONSTRAINT IF EXISTS idempotency_keys_pkey;
+ALTER TABLE idempotency_keys ADD COLUMN IF NOT EXISTS id bigserial PRIMARY KEY;
+-- Requests without an actor share one namespace, user ids start at 1
+CREATE UNIQUE INDEX IF NOT EXISTS idempotency_keys_actor_key
+    ON idempotency_keys(COALESCE(actor_id, 0), key);
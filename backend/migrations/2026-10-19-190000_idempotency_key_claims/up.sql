@@ -0,0 +1,3 @@
+-- An unfinished request's claim on its key lapses after this, so retries are not
+-- blocked forever when the request was abandoned
+ALTER TABLE idempotency_keys ADD COLUMN IF NOT EXISTS locked_until timestamptz;
@@ -1,9 +1,12 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use diesel::{
     pg::{Pg, PgConnection},
     r2d2::{ConnectionManager, Pool, PooledConnection},
+    RunQueryDsl,
 };
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use serde::Deserialize;
+use std::time::Duration;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/");
 
@@ -23,12 +26,101 @@ pub fn get_connection(
     conn
 }
 
-pub fn pool() -> Result<Pool<ConnectionManager<PgConnection>>> {
-    let database_url = std::env::var("DATABASE_URL").context("DATABASE_URL not set")?;
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
+/// Check a connection can be acquired within `timeout` and answers a query
+///
+/// Returns whether any embedded migrations have not been run against the database.
+pub fn check(pool: &Pool<ConnectionManager<PgConnection>>, timeout: Duration) -> Result<bool> {
+    let conn = &mut pool
+        .get_timeout(timeout)
+        .context("Unable to get connection from pool")?;
+    diesel::sql_query("SELECT 1")
+        .execute(conn)
+        .context("Database did not answer a trivial query")?;
+
+    conn.has_pending_migration(MIGRATIONS)
+        .map_err(|e| anyhow!(e))
+        .context("Unable to read migration status")
+}
+
+/// Check a connection out of the pool on tokio's blocking thread pool
+///
+/// Waiting on an exhausted pool blocks for up to the pool's connection timeout,
+/// so this keeps that wait off the async worker threads
+pub async fn acquire(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+) -> Result<PooledConnection<ConnectionManager<PgConnection>>> {
+    let pool = pool.clone();
+
+    tokio::task::spawn_blocking(move || get_connection(&pool))
+        .await
+        .context("Connection checkout task failed")?
+}
+
+/// Run synchronous diesel calls against a pooled connection on tokio's blocking thread pool
+///
+/// Model spans from `f` are children of the caller's current span.
+pub async fn run<F, T>(pool: &Pool<ConnectionManager<PgConnection>>, f: F) -> Result<T>
+where
+    F: FnOnce(&mut PgConnection) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let mut conn = acquire(pool).await?;
+    let span = tracing::Span::current();
+
+    tokio::task::spawn_blocking(move || span.in_scope(|| f(&mut conn)))
+        .await
+        .context("Database task failed")
+}
+
+/// Connection pool settings, read from the `[database]` section of the server config
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    pub url: String,
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    /// How long a checkout waits for a free connection before failing
+    pub connection_timeout_secs: u64,
+    pub idle_timeout_secs: Option<u64>,
+    pub max_lifetime_secs: Option<u64>,
+    /// Run pending migrations at startup
+    pub migrate: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            max_size: 10,
+            min_idle: None,
+            connection_timeout_secs: 30,
+            idle_timeout_secs: Some(600),
+            max_lifetime_secs: Some(1800),
+            migrate: false,
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Defaults with the url taken from `DATABASE_URL`
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            url: std::env::var("DATABASE_URL").context("DATABASE_URL not set")?,
+            ..Self::default()
+        })
+    }
+}
+
+pub fn pool(config: &PoolConfig) -> Result<Pool<ConnectionManager<PgConnection>>> {
+    let manager = ConnectionManager::<PgConnection>::new(&config.url);
 
     let pool = Pool::builder()
         .test_on_check_out(true)
+        .max_size(config.max_size)
+        .min_idle(config.min_idle)
+        .connection_timeout(Duration::from_secs(config.connection_timeout_secs))
+        .idle_timeout(config.idle_timeout_secs.map(Duration::from_secs))
+        .max_lifetime(config.max_lifetime_secs.map(Duration::from_secs))
         .build(manager)
         .context("Unable to establish connection pool");
     log::info!("Created connection pool");
@@ -36,14 +128,11 @@ pub fn pool() -> Result<Pool<ConnectionManager<PgConnection>>> {
     pool
 }
 
-pub fn init() -> Result<Pool<ConnectionManager<PgConnection>>> {
-    let pool = pool()?;
+pub fn init(config: &PoolConfig) -> Result<Pool<ConnectionManager<PgConnection>>> {
+    let pool = pool(config)?;
     let conn = &mut get_connection(&pool)?;
 
-    let migrate: bool = std::env::var("MIGRATE")
-        .unwrap_or("false".to_owned())
-        .parse()?;
-    if migrate {
+    if config.migrate {
         run_migrations(conn);
         log::info!("Attempting to migrate")
     }
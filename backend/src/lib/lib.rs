@@ -1,8 +1,19 @@
 pub mod database;
 mod models;
 mod schema;
+pub mod telemetry;
 
+pub use model_implementations::idempotency_key::IdempotencyClaim;
+pub use model_implementations::merge_patch::InvalidPatch;
+pub use model_implementations::notification::{NotificationEvent, MAX_DELIVERY_ATTEMPTS};
+pub use model_implementations::report_line_item::{BulkOperationFailed, LineItemOperation};
+pub use model_implementations::report_version::{
+    LineItemChange, LineItemSnapshot, ProofSnapshot, ReportSnapshot, ReportVersionDiff,
+    MANUAL_SNAPSHOT,
+};
+pub use model_implementations::row_version::VersionConflict;
 pub use model_implementations::user::UserInfo;
+pub use model_implementations::webhook::MAX_WEBHOOK_ATTEMPTS;
 pub use models::*;
 
 mod model_implementations {
@@ -20,11 +31,32 @@ mod model_implementations {
                 B::default()
             }
         }
+
+        /// Models whose mutations are recorded in the audit log
+        pub trait Audited {
+            /// Name stored in the `entity` column of the audit log
+            const ENTITY: &'static str;
+            /// Fields which are only logged as having changed, never with their values
+            const REDACTED: &'static [&'static str] = &[];
+
+            fn entity_id(&self) -> i64;
+            fn audit_report_id(&self) -> Option<i64>;
+            fn audit_value(&self) -> serde_json::Value;
+        }
     }
 
+    pub mod audit_log;
+    pub mod idempotency_key;
+    mod instrumentation;
+    pub mod merge_patch;
+    pub mod notification;
     pub mod report;
     pub mod report_access;
+    pub mod report_comment;
     pub mod report_line_item;
     pub mod report_proof;
+    pub mod report_version;
+    pub mod row_version;
     pub mod user;
+    pub mod webhook;
 }
@@ -0,0 +1,136 @@
+#![allow(dead_code)]
+
+use super::instrumentation::recorded;
+use super::traits::*;
+use super::{AuditLog, NewAuditLog, WebhookDelivery};
+use anyhow::Result;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use tracing::instrument;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Update => "update",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+/// Hex encoded SHA-256 of binary fields, so changes to them show up in the log without the data
+pub(crate) fn digest(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Field by field difference between two versions of an entity
+///
+/// Only changed fields are included, each as `{"before": .., "after": ..}`.
+pub(crate) fn diff(before: Option<Value>, after: Option<Value>, redacted: &[&str]) -> Value {
+    let into_map = |value: Option<Value>| match value {
+        Some(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    let (before, after) = (into_map(before), into_map(after));
+
+    let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut res = Map::new();
+    for key in keys {
+        let (old, new) = (before.get(key), after.get(key));
+        if old == new {
+            continue;
+        }
+
+        let shown = |value: Option<&Value>| match value {
+            Some(_) if redacted.contains(&key.as_str()) => json!("[redacted]"),
+            Some(value) => value.clone(),
+            None => Value::Null,
+        };
+        res.insert(
+            key.clone(),
+            json!({ "before": shown(old), "after": shown(new) }),
+        );
+    }
+
+    Value::Object(res)
+}
+
+impl AuditLog {
+    /// Append an entry describing a mutation of `T`, and queue it for subscribed webhooks
+    ///
+    /// Should be called inside the same transaction as the mutation itself.
+    #[instrument(name = "AuditLog::record", skip_all, err)]
+    pub(crate) fn record<T: Audited>(
+        action: AuditAction,
+        before: Option<&T>,
+        after: Option<&T>,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<()> {
+        use crate::schema::audit_log::dsl;
+
+        let Some(subject) = after.or(before) else {
+            return Ok(());
+        };
+        let entry = NewAuditLog {
+            actor_id,
+            report_id: subject.audit_report_id(),
+            entity: T::ENTITY.to_owned(),
+            entity_id: subject.entity_id(),
+            action: action.as_str().to_owned(),
+            diff: diff(
+                before.map(T::audit_value),
+                after.map(T::audit_value),
+                T::REDACTED,
+            ),
+        };
+
+        diesel::insert_into(dsl::audit_log)
+            .values(&entry)
+            .execute(conn)?;
+
+        let event = format!("{}.{}", entry.entity, entry.action);
+        let mut data = subject.audit_value();
+        if let Value::Object(map) = &mut data {
+            for key in T::REDACTED {
+                map.remove(*key);
+            }
+        }
+        let payload = json!({
+            "event": event,
+            "occurred_at": chrono::Utc::now(),
+            "actor_id": entry.actor_id,
+            "report_id": entry.report_id,
+            "data": data,
+            "changes": entry.diff,
+        });
+        WebhookDelivery::enqueue(&event, &payload, conn)?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "AuditLog::get_by_report", skip_all, err, fields(db.rows))]
+    pub fn get_by_report(report_id: i64, conn: &mut PgConnection) -> Result<Vec<Self>> {
+        use crate::schema::audit_log::dsl;
+
+        let res = dsl::audit_log
+            .filter(dsl::report_id.eq(report_id))
+            .order(dsl::id.asc())
+            .select(Self::as_select())
+            .load(conn)?;
+
+        recorded(Ok(res))
+    }
+}
@@ -0,0 +1,132 @@
+#![allow(dead_code)]
+
+use super::instrumentation::recorded;
+use super::{IdempotencyKey, NewIdempotencyKey};
+use anyhow::Result;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use tracing::instrument;
+
+/// What to do with a request carrying an idempotency key
+#[derive(Debug, PartialEq)]
+pub enum IdempotencyClaim {
+    /// The key is now held for this request, which should be handled and its response stored
+    New(IdempotencyKey),
+    /// The key was already used for the same request, whose stored response should be returned
+    Replay(IdempotencyKey),
+    /// The first request with the key has not finished yet
+    InProgress,
+    /// The key was already used for a different request
+    Mismatch,
+}
+
+impl IdempotencyKey {
+    /// Claim `key` for a request, unless `actor_id` used it within the last `window`
+    ///
+    /// Keys are scoped to the actor, requests without one share a scope. Keys older
+    /// than `window` are forgotten, so they can be used again. A claim whose request
+    /// has not finished within `lock` lapses, and the next request takes the key over.
+    #[instrument(name = "IdempotencyKey::claim", skip_all, err, fields(db.rows))]
+    pub fn claim(
+        key: &str,
+        actor_id: Option<i64>,
+        request_sha256: &str,
+        window: chrono::Duration,
+        lock: chrono::Duration,
+        conn: &mut PgConnection,
+    ) -> Result<IdempotencyClaim> {
+        use crate::schema::idempotency_keys::dsl;
+
+        recorded(conn.transaction(|conn| {
+            let now = chrono::Utc::now();
+            diesel::delete(dsl::idempotency_keys.filter(dsl::created_at.lt(now - window)))
+                .execute(conn)?;
+
+            let inserted: Option<Self> = diesel::insert_into(dsl::idempotency_keys)
+                .values(&NewIdempotencyKey {
+                    key,
+                    actor_id,
+                    request_sha256,
+                    locked_until: Some(now + lock),
+                })
+                .on_conflict_do_nothing()
+                .get_result(conn)
+                .optional()?;
+            if let Some(claimed) = inserted {
+                return Ok(IdempotencyClaim::New(claimed));
+            }
+
+            let existing: Self = dsl::idempotency_keys
+                .filter(dsl::key.eq(key))
+                .filter(dsl::actor_id.is_not_distinct_from(actor_id))
+                .for_update()
+                .first(conn)?;
+            let lapsed = existing.response_status.is_none()
+                && existing.locked_until.is_none_or(|until| until < now);
+            let res = if lapsed {
+                let claimed = diesel::update(dsl::idempotency_keys.find(existing.id))
+                    .set((
+                        dsl::request_sha256.eq(request_sha256),
+                        dsl::created_at.eq(now),
+                        dsl::locked_until.eq(Some(now + lock)),
+                    ))
+                    .get_result(conn)?;
+                IdempotencyClaim::New(claimed)
+            } else if existing.request_sha256 != request_sha256 {
+                IdempotencyClaim::Mismatch
+            } else if existing.response_status.is_none() {
+                IdempotencyClaim::InProgress
+            } else {
+                IdempotencyClaim::Replay(existing)
+            };
+
+            Ok(res)
+        }))
+    }
+
+    /// Store the response to the request which claimed the key
+    ///
+    /// Does nothing if the claim lapsed and another request took the key over.
+    #[instrument(name = "IdempotencyKey::complete", skip_all, err)]
+    pub fn complete(
+        &self,
+        response_status: i32,
+        response_headers: serde_json::Value,
+        response_body: &[u8],
+        conn: &mut PgConnection,
+    ) -> Result<()> {
+        use crate::schema::idempotency_keys::dsl;
+
+        diesel::update(
+            dsl::idempotency_keys
+                .find(self.id)
+                .filter(dsl::locked_until.is_not_distinct_from(self.locked_until)),
+        )
+        .set((
+            dsl::response_status.eq(Some(response_status)),
+            dsl::response_headers.eq(Some(response_headers)),
+            dsl::response_body.eq(Some(response_body)),
+        ))
+        .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Forget a claimed key whose request failed or was abandoned, so it can be retried
+    ///
+    /// Keys whose response was already stored, or which another request took over, are kept.
+    #[instrument(name = "IdempotencyKey::release", skip_all, err)]
+    pub fn release(&self, conn: &mut PgConnection) -> Result<()> {
+        use crate::schema::idempotency_keys::dsl;
+
+        diesel::delete(
+            dsl::idempotency_keys
+                .find(self.id)
+                .filter(dsl::response_status.is_null())
+                .filter(dsl::locked_until.is_not_distinct_from(self.locked_until)),
+        )
+        .execute(conn)?;
+
+        Ok(())
+    }
+}
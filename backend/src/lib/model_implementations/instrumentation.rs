@@ -0,0 +1,90 @@
+use super::idempotency_key::IdempotencyClaim;
+use super::report_version::{ReportSnapshot, ReportVersionDiff};
+use super::user::UserInfo;
+use super::*;
+use anyhow::Result;
+use tracing::Span;
+
+/// Rows a model call returned or changed, recorded on its span as `db.rows`
+pub(crate) trait Rows {
+    /// `None` when the call does not map onto a number of rows
+    fn rows(&self) -> Option<usize>;
+}
+
+impl<T> Rows for Vec<T> {
+    fn rows(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<T> Rows for Option<T> {
+    fn rows(&self) -> Option<usize> {
+        Some(self.is_some().into())
+    }
+}
+
+/// Rows affected by an `UPDATE` or `DELETE`
+impl Rows for usize {
+    fn rows(&self) -> Option<usize> {
+        Some(*self)
+    }
+}
+
+/// Assembled from several queries
+impl Rows for ReportSnapshot {
+    fn rows(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl Rows for ReportVersionDiff {
+    fn rows(&self) -> Option<usize> {
+        None
+    }
+}
+
+macro_rules! single_row {
+    ($($model:ty),* $(,)?) => {
+        $(
+            impl Rows for $model {
+                fn rows(&self) -> Option<usize> {
+                    Some(1)
+                }
+            }
+        )*
+    };
+}
+
+single_row!(
+    AuditLog,
+    IdempotencyClaim,
+    Notification,
+    NotificationPreferences,
+    OutboxMessage,
+    Report,
+    ReportAccess,
+    ReportComment,
+    ReportLineItem,
+    ReportProof,
+    ReportVersion,
+    User,
+    UserInfo,
+    Webhook,
+    WebhookDelivery,
+    axum::body::Bytes,
+    // Aggregates and existence checks
+    bool,
+    i64,
+);
+
+/// Record the row count of a successful model call on the current span
+///
+/// Errors are recorded by `#[instrument(err)]` on the call itself.
+pub(crate) fn recorded<T: Rows>(res: Result<T>) -> Result<T> {
+    if let Some(rows) = res.as_ref().ok().and_then(Rows::rows) {
+        // Unsigned fields are exported as strings
+        Span::current().record("db.rows", rows as i64);
+    }
+
+    res
+}
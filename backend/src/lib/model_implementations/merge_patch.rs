@@ -0,0 +1,115 @@
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// A merge patch which touches fields clients may not change, or leaves a field invalid
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidPatch(pub String);
+
+impl std::fmt::Display for InvalidPatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid merge patch: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPatch {}
+
+/// Apply a JSON Merge Patch (RFC 7396) to the client-mutable fields of a resource
+///
+/// `T` lists those fields and should deny unknown fields, so that patches
+/// mentioning anything else are rejected rather than ignored.
+pub(crate) fn apply<T: Serialize + DeserializeOwned>(current: &T, patch: &Value) -> Result<T> {
+    let mut target = serde_json::to_value(current)?;
+    merge(&mut target, patch);
+
+    let res = serde_json::from_value(target).map_err(|e| InvalidPatch(e.to_string()))?;
+
+    Ok(res)
+}
+
+fn merge(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+
+    let Value::Object(target) = target else {
+        unreachable!()
+    };
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    struct Fields {
+        name: String,
+        note: Option<String>,
+        tags: Value,
+    }
+
+    fn current() -> Fields {
+        Fields {
+            name: "name".to_owned(),
+            note: Some("note".to_owned()),
+            tags: json!({"a": 1, "b": 2}),
+        }
+    }
+
+    #[test]
+    fn empty_patch_changes_nothing() {
+        assert_eq!(apply(&current(), &json!({})).unwrap(), current());
+    }
+
+    #[test]
+    fn patch_replaces_mentioned_fields_only() {
+        let res = apply(&current(), &json!({"name": "renamed"})).unwrap();
+        assert_eq!(res.name, "renamed");
+        assert_eq!(res.note, current().note);
+        assert_eq!(res.tags, current().tags);
+    }
+
+    #[test]
+    fn null_removes_a_field() {
+        let res = apply(&current(), &json!({"note": null})).unwrap();
+        assert_eq!(res.note, None);
+    }
+
+    #[test]
+    fn objects_are_merged_recursively() {
+        let res = apply(&current(), &json!({"tags": {"a": null, "c": 3}})).unwrap();
+        assert_eq!(res.tags, json!({"b": 2, "c": 3}));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let err = apply(&current(), &json!({"owner_id": 1})).unwrap_err();
+        assert!(err.downcast_ref::<InvalidPatch>().is_some());
+    }
+
+    #[test]
+    fn removing_a_required_field_is_rejected() {
+        let err = apply(&current(), &json!({"name": null})).unwrap_err();
+        assert!(err.downcast_ref::<InvalidPatch>().is_some());
+    }
+
+    #[test]
+    fn non_object_patch_is_rejected() {
+        // It replaces the whole resource, which then no longer deserializes
+        assert!(apply(&current(), &json!("name")).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+#![allow(dead_code)]
+
+use super::instrumentation::recorded;
+use super::{
+    NewNotification, NewOutboxMessage, Notification, NotificationPreferences, OutboxMessage,
+    Report, User,
+};
+use anyhow::Result;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+/// Number of failed deliveries after which a message is no longer retried
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 5;
+
+/// Events users can be notified about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    ReportShared,
+    ReportSubmitted,
+    ReportApproved,
+    ReportRejected,
+    CommentAdded,
+}
+
+impl NotificationEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ReportShared => "report_shared",
+            Self::ReportSubmitted => "report_submitted",
+            Self::ReportApproved => "report_approved",
+            Self::ReportRejected => "report_rejected",
+            Self::CommentAdded => "comment_added",
+        }
+    }
+
+    /// Subject and body of the message sent for this event
+    pub fn render(&self, report: &Report, actor_name: Option<&str>) -> (String, String) {
+        let actor = actor_name.unwrap_or("Someone");
+        let title = &report.title;
+
+        let (subject, summary) = match self {
+            Self::ReportShared => (
+                format!("{actor} shared \"{title}\" with you"),
+                format!("{actor} gave you access to the expense report \"{title}\"."),
+            ),
+            Self::ReportSubmitted => (
+                format!("\"{title}\" was submitted for approval"),
+                format!("{actor} submitted the expense report \"{title}\" for approval."),
+            ),
+            Self::ReportApproved => (
+                format!("\"{title}\" was approved"),
+                format!("{actor} approved the expense report \"{title}\"."),
+            ),
+            Self::ReportRejected => (
+                format!("\"{title}\" was rejected"),
+                format!("{actor} rejected the expense report \"{title}\"."),
+            ),
+            Self::CommentAdded => (
+                format!("{actor} commented on \"{title}\""),
+                format!("{actor} left a comment on the expense report \"{title}\"."),
+            ),
+        };
+        let body = format!(
+            "{summary}\n\nReport: {title} (#{})\n\nYou can change which emails you receive in your notification preferences.\n",
+            report.id
+        );
+
+        (subject, body)
+    }
+}
+
+impl NotificationPreferences {
+    /// Preferences used for users who have not saved any
+    pub fn defaults(user_id: i64) -> Self {
+        Self {
+            user_id,
+            email_enabled: true,
+            report_shared: true,
+            report_submitted: true,
+            report_approved: true,
+            report_rejected: true,
+            comment_added: true,
+        }
+    }
+
+    #[instrument(name = "NotificationPreferences::get_by_user", skip_all, err, fields(db.rows))]
+    pub fn get_by_user(user_id: i64, conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::notification_preferences::dsl;
+
+        let res = dsl::notification_preferences
+            .filter(dsl::user_id.eq(user_id))
+            .first(conn)
+            .optional()?;
+
+        recorded(Ok(res.unwrap_or_else(|| Self::defaults(user_id))))
+    }
+
+    #[instrument(name = "NotificationPreferences::upsert", skip_all, err, fields(db.rows))]
+    pub fn upsert(&self, conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::notification_preferences::dsl;
+
+        let res = diesel::insert_into(dsl::notification_preferences)
+            .values(self)
+            .on_conflict(dsl::user_id)
+            .do_update()
+            .set(self)
+            .get_result(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    pub fn wants(&self, event: NotificationEvent) -> bool {
+        self.email_enabled
+            && match event {
+                NotificationEvent::ReportShared => self.report_shared,
+                NotificationEvent::ReportSubmitted => self.report_submitted,
+                NotificationEvent::ReportApproved => self.report_approved,
+                NotificationEvent::ReportRejected => self.report_rejected,
+                NotificationEvent::CommentAdded => self.comment_added,
+            }
+    }
+}
+
+impl Notification {
+    /// Tell a user about an event in their inbox, and by email if they want it
+    ///
+    /// Users are not notified of events they caused themselves.
+    /// Should be called inside the transaction making the change being notified about.
+    #[instrument(name = "Notification::notify", skip_all, err)]
+    pub fn notify(
+        user_id: i64,
+        event: NotificationEvent,
+        report: &Report,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<()> {
+        use crate::schema::notifications::dsl;
+
+        if actor_id == Some(user_id) {
+            return Ok(());
+        }
+
+        let actor_name = match actor_id {
+            Some(id) => Some(User::get_full_by_id(id, conn)?.username),
+            None => None,
+        };
+        let (message, _) = event.render(report, actor_name.as_deref());
+        let new = NewNotification {
+            user_id,
+            event: event.as_str().to_owned(),
+            report_id: Some(report.id),
+            actor_id,
+            message,
+        };
+        diesel::insert_into(dsl::notifications)
+            .values(&new)
+            .execute(conn)?;
+
+        OutboxMessage::enqueue(user_id, event, report, actor_id, conn)?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "Notification::get_by_user", skip_all, err, fields(db.rows))]
+    pub fn get_by_user(
+        user_id: i64,
+        include_read: bool,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<Self>> {
+        use crate::schema::notifications::dsl;
+
+        let mut query = dsl::notifications
+            .filter(dsl::user_id.eq(user_id))
+            .order(dsl::created_at.desc())
+            .select(Self::as_select())
+            .into_boxed();
+        if !include_read {
+            query = query.filter(dsl::read_at.is_null());
+        }
+        let res = query.load(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "Notification::count_unread", skip_all, err, fields(db.rows))]
+    pub fn count_unread(user_id: i64, conn: &mut PgConnection) -> Result<i64> {
+        use crate::schema::notifications::dsl;
+
+        let res = dsl::notifications
+            .filter(dsl::user_id.eq(user_id))
+            .filter(dsl::read_at.is_null())
+            .count()
+            .get_result(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "Notification::mark_read", skip_all, err, fields(db.rows))]
+    pub fn mark_read(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::notifications::dsl;
+
+        let res = diesel::update(
+            dsl::notifications
+                .filter(dsl::user_id.eq(path_ids.0))
+                .filter(dsl::id.eq(path_ids.1)),
+        )
+        .set(dsl::read_at.eq(Some(chrono::Utc::now())))
+        .get_result(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "Notification::mark_all_read", skip_all, err, fields(db.rows))]
+    pub fn mark_all_read(user_id: i64, conn: &mut PgConnection) -> Result<usize> {
+        use crate::schema::notifications::dsl;
+
+        let res = diesel::update(
+            dsl::notifications
+                .filter(dsl::user_id.eq(user_id))
+                .filter(dsl::read_at.is_null()),
+        )
+        .set(dsl::read_at.eq(Some(chrono::Utc::now())))
+        .execute(conn)?;
+
+        recorded(Ok(res))
+    }
+}
+
+impl OutboxMessage {
+    /// Queue an email to a user about an event on a report
+    ///
+    /// Nothing is queued if the user has opted out of emails for the event.
+    #[instrument(name = "OutboxMessage::enqueue", skip_all, err, fields(db.rows))]
+    pub fn enqueue(
+        user_id: i64,
+        event: NotificationEvent,
+        report: &Report,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Option<Self>> {
+        use crate::schema::notification_outbox::dsl;
+
+        if !NotificationPreferences::get_by_user(user_id, conn)?.wants(event) {
+            return Ok(None);
+        }
+
+        let recipient = User::get_full_by_id(user_id, conn)?;
+        let actor_name = match actor_id {
+            Some(id) => Some(User::get_full_by_id(id, conn)?.username),
+            None => None,
+        };
+        let (subject, body) = event.render(report, actor_name.as_deref());
+
+        let new = NewOutboxMessage {
+            user_id,
+            event: event.as_str().to_owned(),
+            recipient: recipient.email,
+            subject,
+            body,
+        };
+        let res = diesel::insert_into(dsl::notification_outbox)
+            .values(&new)
+            .get_result(conn)?;
+
+        recorded(Ok(Some(res)))
+    }
+
+    /// Lock up to `limit` undelivered messages for `lease_seconds`
+    ///
+    /// Locked rows are skipped rather than waited on, so several workers can share the outbox.
+    #[instrument(name = "OutboxMessage::claim_pending", skip_all, err, fields(db.rows))]
+    pub fn claim_pending(
+        limit: i64,
+        lease_seconds: f64,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<Self>> {
+        use diesel::sql_types::{BigInt, Double, Integer};
+
+        let res = diesel::sql_query(
+            "UPDATE notification_outbox SET locked_until = now() + make_interval(secs => $1)
+            WHERE id IN (
+                SELECT id FROM notification_outbox
+                WHERE sent_at IS NULL
+                    AND attempts < $2
+                    AND (locked_until IS NULL OR locked_until < now())
+                ORDER BY id
+                LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *",
+        )
+        .bind::<Double, _>(lease_seconds)
+        .bind::<Integer, _>(MAX_DELIVERY_ATTEMPTS)
+        .bind::<BigInt, _>(limit)
+        .load(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    /// Messages still waiting to be sent, including ones being retried
+    #[instrument(name = "OutboxMessage::count_pending", skip_all, err, fields(db.rows))]
+    pub fn count_pending(conn: &mut PgConnection) -> Result<i64> {
+        use crate::schema::notification_outbox::dsl;
+
+        let res = dsl::notification_outbox
+            .filter(dsl::sent_at.is_null())
+            .filter(dsl::attempts.lt(MAX_DELIVERY_ATTEMPTS))
+            .count()
+            .get_result(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "OutboxMessage::mark_sent", skip_all, err)]
+    pub fn mark_sent(&self, conn: &mut PgConnection) -> Result<()> {
+        use crate::schema::notification_outbox::dsl;
+
+        diesel::update(dsl::notification_outbox.filter(dsl::id.eq(self.id)))
+            .set((
+                dsl::sent_at.eq(Some(chrono::Utc::now())),
+                dsl::locked_until.eq(None::<chrono::DateTime<chrono::Utc>>),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Record a failed delivery, delaying the next attempt exponentially
+    #[instrument(name = "OutboxMessage::mark_failed", skip_all, err)]
+    pub fn mark_failed(&self, error: &str, conn: &mut PgConnection) -> Result<()> {
+        use crate::schema::notification_outbox::dsl;
+
+        let attempts = self.attempts + 1;
+        let retry_at = chrono::Utc::now() + chrono::Duration::minutes(1 << attempts.min(10));
+
+        diesel::update(dsl::notification_outbox.filter(dsl::id.eq(self.id)))
+            .set((
+                dsl::attempts.eq(attempts),
+                dsl::last_error.eq(Some(error)),
+                dsl::locked_until.eq(Some(retry_at)),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
@@ -1,10 +1,16 @@
 #![allow(dead_code)]
 
+use super::audit_log::AuditAction;
+use super::instrumentation::recorded;
+use super::merge_patch;
+use super::row_version;
 use super::traits::*;
-use super::{NewReport, Report, User};
+use super::{AuditLog, NewReport, Report, ReportPatch, User};
 use anyhow::Result;
 use diesel::prelude::*;
 use diesel::PgConnection;
+use serde_json::Value;
+use tracing::instrument;
 
 #[derive(Default, Debug)]
 pub struct NewReportBuilder {
@@ -48,35 +54,76 @@ impl NewReportBuilder {
 
 impl HasBuilder<NewReportBuilder, Self> for NewReport {}
 impl NewReport {
-    pub fn insert(&self, conn: &mut PgConnection) -> Result<Report> {
+    #[instrument(name = "NewReport::insert", skip_all, err, fields(db.rows))]
+    pub fn insert(&self, actor_id: Option<i64>, conn: &mut PgConnection) -> Result<Report> {
         use crate::schema::reports::dsl;
 
-        let res = diesel::insert_into(dsl::reports)
-            .values(self)
-            .get_result(conn)?;
+        recorded(conn.transaction(|conn| {
+            let res: Report = diesel::insert_into(dsl::reports)
+                .values(self)
+                .get_result(conn)?;
+            AuditLog::record(AuditAction::Create, None, Some(&res), actor_id, conn)?;
 
-        Ok(res)
+            Ok(res)
+        }))
+    }
+}
+
+impl Audited for Report {
+    const ENTITY: &'static str = "report";
+
+    fn entity_id(&self) -> i64 {
+        self.id
+    }
+
+    fn audit_report_id(&self) -> Option<i64> {
+        Some(self.id)
+    }
+
+    fn audit_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
     }
 }
 
 impl HasBuilder<NewReportBuilder, NewReport> for Report {}
 impl Report {
-    pub fn clear(conn: &mut PgConnection) -> Result<()> {
+    #[instrument(name = "Report::clear", skip_all, err)]
+    pub fn clear(actor_id: Option<i64>, conn: &mut PgConnection) -> Result<()> {
         use crate::schema::reports::dsl;
 
-        diesel::delete(dsl::reports).execute(conn)?;
+        conn.transaction(|conn| {
+            let deleted: Vec<Self> = diesel::delete(dsl::reports).get_results(conn)?;
+            for report in &deleted {
+                AuditLog::record(AuditAction::Delete, Some(report), None, actor_id, conn)?;
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
+    #[instrument(name = "Report::get_by_id", skip_all, err, fields(db.rows))]
     pub fn get_by_id(id: i64, conn: &mut PgConnection) -> Result<Self> {
         use crate::schema::reports::dsl;
 
         let res = dsl::reports.filter(dsl::id.eq(id)).first(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    /// Like [`Report::get_by_id`], but holds a row lock until the end of the transaction
+    #[instrument(name = "Report::lock_by_id", skip_all, err, fields(db.rows))]
+    pub(crate) fn lock_by_id(id: i64, conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::reports::dsl;
+
+        let res = dsl::reports
+            .filter(dsl::id.eq(id))
+            .for_update()
+            .first(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "Report::get_by_owner", skip_all, err, fields(db.rows))]
     pub fn get_by_owner(owner_id: i64, conn: &mut PgConnection) -> Result<Vec<Self>> {
         use crate::schema::reports::dsl;
 
@@ -85,44 +132,117 @@ impl Report {
             .select(Self::as_select())
             .load(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
-    pub fn delete(id: i64, conn: &mut PgConnection) -> Result<Self> {
+    #[instrument(name = "Report::count", skip_all, err, fields(db.rows))]
+    pub fn count(conn: &mut PgConnection) -> Result<i64> {
         use crate::schema::reports::dsl;
 
-        let res = diesel::delete(dsl::reports.filter(dsl::id.eq(id))).get_result(conn)?;
+        let res = dsl::reports.count().get_result(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "Report::delete", skip_all, err, fields(db.rows))]
+    pub fn delete(
+        id: i64,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
+        use crate::schema::reports::dsl;
+
+        recorded(conn.transaction(|conn| {
+            row_version::check(Self::lock_by_id(id, conn)?.version, expected_version)?;
+            let res: Self = diesel::delete(dsl::reports.filter(dsl::id.eq(id))).get_result(conn)?;
+            AuditLog::record(AuditAction::Delete, Some(&res), None, actor_id, conn)?;
+
+            Ok(res)
+        }))
+    }
+
+    #[instrument(name = "Report::update", skip_all, err, fields(db.rows))]
     pub fn update(
         id: i64,
         owner_id: i64,
         title: String,
         description: Option<String>,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<Self> {
         use crate::schema::reports::dsl;
 
-        let res = diesel::update(dsl::reports.filter(dsl::id.eq(id)))
-            .set((
-                dsl::owner_id.eq(owner_id),
-                dsl::title.eq(title),
-                dsl::description.eq(description),
-            ))
-            .get_result(conn)?;
-
-        Ok(res)
+        recorded(conn.transaction(|conn| {
+            let before = Self::lock_by_id(id, conn)?;
+            row_version::check(before.version, expected_version)?;
+            let res: Self = diesel::update(dsl::reports.filter(dsl::id.eq(id)))
+                .set((
+                    dsl::owner_id.eq(owner_id),
+                    dsl::title.eq(title),
+                    dsl::description.eq(description),
+                ))
+                .get_result(conn)?;
+            AuditLog::record(
+                AuditAction::Update,
+                Some(&before),
+                Some(&res),
+                actor_id,
+                conn,
+            )?;
+
+            Ok(res)
+        }))
     }
 
-    pub fn replace(id: i64, new: &NewReport, conn: &mut PgConnection) -> Result<Self> {
-        Self::update(
+    #[instrument(name = "Report::replace", skip_all, err, fields(db.rows))]
+    pub fn replace(
+        id: i64,
+        new: &NewReport,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
+        recorded(Self::update(
             id,
             new.owner_id,
             new.title.clone(),
             new.description.clone(),
+            expected_version,
+            actor_id,
             conn,
-        )
+        ))
+    }
+
+    /// Apply a JSON Merge Patch to the title and description of a report
+    #[instrument(name = "Report::patch", skip_all, err, fields(db.rows))]
+    pub fn patch(
+        id: i64,
+        patch: &Value,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
+        recorded(conn.transaction(|conn| {
+            let current = Self::lock_by_id(id, conn)?;
+            row_version::check(current.version, expected_version)?;
+
+            let fields = ReportPatch {
+                title: current.title,
+                description: current.description,
+            };
+            let changes = merge_patch::apply(&fields, patch)?;
+
+            Self::update(
+                id,
+                current.owner_id,
+                changes.title,
+                changes.description,
+                None,
+                actor_id,
+                conn,
+            )
+        }))
     }
 }
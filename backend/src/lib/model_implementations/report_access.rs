@@ -1,10 +1,19 @@
 #![allow(dead_code)]
 
+use super::audit_log::AuditAction;
+use super::instrumentation::recorded;
+use super::merge_patch;
+use super::notification::NotificationEvent;
+use super::row_version;
 use super::traits::*;
-use super::{NewReportAccess, Report, ReportAccess, User};
+use super::{
+    AuditLog, NewReportAccess, Notification, Report, ReportAccess, ReportAccessPatch, User,
+};
 use anyhow::Result;
 use diesel::prelude::*;
 use diesel::PgConnection;
+use serde_json::Value;
+use tracing::instrument;
 
 #[derive(Default, Debug)]
 pub struct NewReportAccessBuilder {
@@ -65,35 +74,83 @@ impl NewReportAccessBuilder {
 
 impl HasBuilder<NewReportAccessBuilder, Self> for NewReportAccess {}
 impl NewReportAccess {
-    pub fn insert(&self, conn: &mut PgConnection) -> Result<ReportAccess> {
+    #[instrument(name = "NewReportAccess::insert", skip_all, err, fields(db.rows))]
+    pub fn insert(&self, actor_id: Option<i64>, conn: &mut PgConnection) -> Result<ReportAccess> {
         use crate::schema::report_access::dsl;
 
-        let res = diesel::insert_into(dsl::report_access)
-            .values(self)
-            .get_result(conn)?;
+        recorded(conn.transaction(|conn| {
+            let res: ReportAccess = diesel::insert_into(dsl::report_access)
+                .values(self)
+                .get_result(conn)?;
+            AuditLog::record(AuditAction::Create, None, Some(&res), actor_id, conn)?;
+
+            let report = Report::get_by_id(res.report_id, conn)?;
+            Notification::notify(
+                res.borrower_id,
+                NotificationEvent::ReportShared,
+                &report,
+                actor_id,
+                conn,
+            )?;
+
+            Ok(res)
+        }))
+    }
+}
+
+impl Audited for ReportAccess {
+    const ENTITY: &'static str = "report_access";
+
+    fn entity_id(&self) -> i64 {
+        self.id
+    }
+
+    fn audit_report_id(&self) -> Option<i64> {
+        Some(self.report_id)
+    }
 
-        Ok(res)
+    fn audit_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
     }
 }
 
 impl HasBuilder<NewReportAccessBuilder, NewReportAccess> for ReportAccess {}
 impl ReportAccess {
-    pub fn clear(conn: &mut PgConnection) -> Result<()> {
+    #[instrument(name = "ReportAccess::clear", skip_all, err)]
+    pub fn clear(actor_id: Option<i64>, conn: &mut PgConnection) -> Result<()> {
         use crate::schema::report_access::dsl;
 
-        diesel::delete(dsl::report_access).execute(conn)?;
+        conn.transaction(|conn| {
+            let deleted: Vec<Self> = diesel::delete(dsl::report_access).get_results(conn)?;
+            for access in &deleted {
+                AuditLog::record(AuditAction::Delete, Some(access), None, actor_id, conn)?;
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
-    pub fn clear_by_report(report_id: i64, conn: &mut PgConnection) -> Result<()> {
+    #[instrument(name = "ReportAccess::clear_by_report", skip_all, err)]
+    pub fn clear_by_report(
+        report_id: i64,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<()> {
         use crate::schema::report_access::dsl;
 
-        diesel::delete(dsl::report_access.filter(dsl::report_id.eq(report_id))).execute(conn)?;
+        conn.transaction(|conn| {
+            let deleted: Vec<Self> =
+                diesel::delete(dsl::report_access.filter(dsl::report_id.eq(report_id)))
+                    .get_results(conn)?;
+            for access in &deleted {
+                AuditLog::record(AuditAction::Delete, Some(access), None, actor_id, conn)?;
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
+    #[instrument(name = "ReportAccess::get_by_path", skip_all, err, fields(db.rows))]
     pub fn get_by_path(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
         use crate::schema::report_access::dsl;
 
@@ -102,17 +159,19 @@ impl ReportAccess {
             .filter(dsl::id.eq(path_ids.1))
             .first(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "ReportAccess::get_by_id", skip_all, err, fields(db.rows))]
     pub fn get_by_id(id: i64, conn: &mut PgConnection) -> Result<Self> {
         use crate::schema::report_access::dsl;
 
         let res = dsl::report_access.filter(dsl::id.eq(id)).first(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "ReportAccess::get_by_report", skip_all, err, fields(db.rows))]
     pub fn get_by_report(report_id: i64, conn: &mut PgConnection) -> Result<Vec<Self>> {
         use crate::schema::report_access::dsl;
 
@@ -121,9 +180,10 @@ impl ReportAccess {
             .select(Self::as_select())
             .load(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "ReportAccess::get_by_borrower", skip_all, err, fields(db.rows))]
     pub fn get_by_borrower(borrower_id: i64, conn: &mut PgConnection) -> Result<Vec<Self>> {
         use crate::schema::report_access::dsl;
 
@@ -132,9 +192,10 @@ impl ReportAccess {
             .select(Self::as_select())
             .load(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "ReportAccess::get_by_view_access", skip_all, err, fields(db.rows))]
     pub fn get_by_view_access(borrower_id: i64, conn: &mut PgConnection) -> Result<Vec<Self>> {
         use crate::schema::report_access::dsl;
 
@@ -144,9 +205,68 @@ impl ReportAccess {
             .select(Self::as_select())
             .load(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
+    }
+
+    /// Ids of every user who can read a report, starting with its owner
+    #[instrument(name = "ReportAccess::get_readers", skip_all, err, fields(db.rows))]
+    pub fn get_readers(report_id: i64, conn: &mut PgConnection) -> Result<Vec<i64>> {
+        use crate::schema::report_access::dsl;
+
+        let mut res = vec![Report::get_by_id(report_id, conn)?.owner_id];
+        let borrowers: Vec<i64> = dsl::report_access
+            .filter(dsl::report_id.eq(report_id))
+            .filter(dsl::read_access.or(dsl::write_access))
+            .select(dsl::borrower_id)
+            .load(conn)?;
+        for borrower_id in borrowers {
+            if !res.contains(&borrower_id) {
+                res.push(borrower_id);
+            }
+        }
+
+        recorded(Ok(res))
+    }
+
+    /// Whether a user owns a report or has been granted read or write access to it
+    #[instrument(name = "ReportAccess::can_read", skip_all, err, fields(db.rows))]
+    pub fn can_read(report_id: i64, user_id: i64, conn: &mut PgConnection) -> Result<bool> {
+        use crate::schema::report_access::dsl;
+
+        if Report::get_by_id(report_id, conn)?.owner_id == user_id {
+            return Ok(true);
+        }
+        let res = diesel::select(diesel::dsl::exists(
+            dsl::report_access
+                .filter(dsl::report_id.eq(report_id))
+                .filter(dsl::borrower_id.eq(user_id))
+                .filter(dsl::read_access.or(dsl::write_access)),
+        ))
+        .get_result(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    /// Whether a user owns a report or has been granted write access to it
+    #[instrument(name = "ReportAccess::can_write", skip_all, err, fields(db.rows))]
+    pub fn can_write(report_id: i64, user_id: i64, conn: &mut PgConnection) -> Result<bool> {
+        use crate::schema::report_access::dsl;
+
+        if Report::get_by_id(report_id, conn)?.owner_id == user_id {
+            return Ok(true);
+        }
+        let res = diesel::select(diesel::dsl::exists(
+            dsl::report_access
+                .filter(dsl::report_id.eq(report_id))
+                .filter(dsl::borrower_id.eq(user_id))
+                .filter(dsl::write_access.eq(true)),
+        ))
+        .get_result(conn)?;
+
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "ReportAccess::get_report_by_borrower", skip_all, err, fields(db.rows))]
     pub fn get_report_by_borrower(
         borrower_id: i64,
         conn: &mut PgConnection,
@@ -162,9 +282,10 @@ impl ReportAccess {
             .select(Report::as_select())
             .load(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "ReportAccess::get_report_by_read_access", skip_all, err, fields(db.rows))]
     pub fn get_report_by_read_access(
         borrower_id: i64,
         conn: &mut PgConnection,
@@ -180,60 +301,139 @@ impl ReportAccess {
             .select(Report::as_select())
             .load(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
-    pub fn delete(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
+    /// Like [`ReportAccess::get_by_path`], but holds a row lock until the end of the transaction
+    #[instrument(name = "ReportAccess::lock_by_path", skip_all, err, fields(db.rows))]
+    fn lock_by_path(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
         use crate::schema::report_access::dsl;
 
-        let res = diesel::delete(
-            dsl::report_access
-                .filter(dsl::report_id.eq(path_ids.0))
-                .filter(dsl::id.eq(path_ids.1)),
-        )
-        .get_result(conn)?;
+        let res = dsl::report_access
+            .filter(dsl::report_id.eq(path_ids.0))
+            .filter(dsl::id.eq(path_ids.1))
+            .for_update()
+            .first(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "ReportAccess::delete", skip_all, err, fields(db.rows))]
+    pub fn delete(
+        path_ids: (i64, i64),
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
+        use crate::schema::report_access::dsl;
+
+        recorded(conn.transaction(|conn| {
+            row_version::check(
+                Self::lock_by_path(path_ids, conn)?.version,
+                expected_version,
+            )?;
+            let res: Self = diesel::delete(
+                dsl::report_access
+                    .filter(dsl::report_id.eq(path_ids.0))
+                    .filter(dsl::id.eq(path_ids.1)),
+            )
+            .get_result(conn)?;
+            AuditLog::record(AuditAction::Delete, Some(&res), None, actor_id, conn)?;
+
+            Ok(res)
+        }))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(name = "ReportAccess::update", skip_all, err, fields(db.rows))]
     pub fn update(
         path_ids: (i64, i64),
         borrower_id: i64,
         report_id: i64,
         read_access: bool,
         write_access: bool,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<Self> {
         use crate::schema::report_access::dsl;
 
-        let res = diesel::update(
-            dsl::report_access
-                .filter(dsl::report_id.eq(path_ids.0))
-                .filter(dsl::id.eq(path_ids.1)),
-        )
-        .set((
-            dsl::borrower_id.eq(borrower_id),
-            dsl::report_id.eq(report_id),
-            dsl::read_access.eq(read_access),
-            dsl::write_access.eq(write_access),
-        ))
-        .get_result(conn)?;
-
-        Ok(res)
+        recorded(conn.transaction(|conn| {
+            let before = Self::lock_by_path(path_ids, conn)?;
+            row_version::check(before.version, expected_version)?;
+            let res: Self = diesel::update(
+                dsl::report_access
+                    .filter(dsl::report_id.eq(path_ids.0))
+                    .filter(dsl::id.eq(path_ids.1)),
+            )
+            .set((
+                dsl::borrower_id.eq(borrower_id),
+                dsl::report_id.eq(report_id),
+                dsl::read_access.eq(read_access),
+                dsl::write_access.eq(write_access),
+            ))
+            .get_result(conn)?;
+            AuditLog::record(
+                AuditAction::Update,
+                Some(&before),
+                Some(&res),
+                actor_id,
+                conn,
+            )?;
+
+            Ok(res)
+        }))
     }
 
+    #[instrument(name = "ReportAccess::replace", skip_all, err, fields(db.rows))]
     pub fn replace(
         path_ids: (i64, i64),
         new: &NewReportAccess,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<Self> {
-        Self::update(
+        recorded(Self::update(
             path_ids,
             new.borrower_id,
             new.report_id,
             new.read_access,
             new.write_access,
+            expected_version,
+            actor_id,
             conn,
-        )
+        ))
+    }
+
+    /// Apply a JSON Merge Patch to the permissions of an access grant
+    #[instrument(name = "ReportAccess::patch", skip_all, err, fields(db.rows))]
+    pub fn patch(
+        path_ids: (i64, i64),
+        patch: &Value,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
+        recorded(conn.transaction(|conn| {
+            let current = Self::lock_by_path(path_ids, conn)?;
+            row_version::check(current.version, expected_version)?;
+
+            let fields = ReportAccessPatch {
+                read_access: current.read_access,
+                write_access: current.write_access,
+            };
+            let changes = merge_patch::apply(&fields, patch)?;
+
+            Self::update(
+                path_ids,
+                current.borrower_id,
+                current.report_id,
+                changes.read_access,
+                changes.write_access,
+                None,
+                actor_id,
+                conn,
+            )
+        }))
     }
 }
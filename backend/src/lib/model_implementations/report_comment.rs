@@ -0,0 +1,194 @@
+#![allow(dead_code)]
+
+use super::audit_log::AuditAction;
+use super::instrumentation::recorded;
+use super::notification::NotificationEvent;
+use super::traits::*;
+use super::{
+    AuditLog, NewReportComment, Notification, Report, ReportAccess, ReportComment, ReportLineItem,
+};
+use anyhow::{ensure, Result};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use tracing::instrument;
+
+impl NewReportComment {
+    #[instrument(name = "NewReportComment::insert", skip_all, err, fields(db.rows))]
+    pub fn insert(&self, actor_id: Option<i64>, conn: &mut PgConnection) -> Result<ReportComment> {
+        use crate::schema::report_comments::dsl;
+
+        recorded(conn.transaction(|conn| {
+            if let Some(line_item_id) = self.line_item_id {
+                // Fails if the item belongs to a different report
+                ReportLineItem::get_by_path((self.report_id, line_item_id), conn)?;
+            }
+
+            let res: ReportComment = diesel::insert_into(dsl::report_comments)
+                .values(self)
+                .get_result(conn)?;
+            AuditLog::record(AuditAction::Create, None, Some(&res), actor_id, conn)?;
+
+            let report = Report::get_by_id(res.report_id, conn)?;
+            for reader_id in ReportAccess::get_readers(res.report_id, conn)? {
+                Notification::notify(
+                    reader_id,
+                    NotificationEvent::CommentAdded,
+                    &report,
+                    Some(res.author_id),
+                    conn,
+                )?;
+            }
+
+            Ok(res)
+        }))
+    }
+}
+
+impl Audited for ReportComment {
+    const ENTITY: &'static str = "report_comment";
+
+    fn entity_id(&self) -> i64 {
+        self.id
+    }
+
+    fn audit_report_id(&self) -> Option<i64> {
+        Some(self.report_id)
+    }
+
+    fn audit_value(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "report_id": self.report_id,
+            "line_item_id": self.line_item_id,
+            "author_id": self.author_id,
+            "body": self.body,
+        })
+    }
+}
+
+impl ReportComment {
+    #[instrument(name = "ReportComment::get_by_report", skip_all, err, fields(db.rows))]
+    pub fn get_by_report(report_id: i64, conn: &mut PgConnection) -> Result<Vec<Self>> {
+        use crate::schema::report_comments::dsl;
+
+        let res = dsl::report_comments
+            .filter(dsl::report_id.eq(report_id))
+            .order(dsl::created_at.asc())
+            .select(Self::as_select())
+            .load(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "ReportComment::get_by_line_item", skip_all, err, fields(db.rows))]
+    pub fn get_by_line_item(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Vec<Self>> {
+        use crate::schema::report_comments::dsl;
+
+        let res = dsl::report_comments
+            .filter(dsl::report_id.eq(path_ids.0))
+            .filter(dsl::line_item_id.eq(path_ids.1))
+            .order(dsl::created_at.asc())
+            .select(Self::as_select())
+            .load(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "ReportComment::get_by_path", skip_all, err, fields(db.rows))]
+    pub fn get_by_path(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::report_comments::dsl;
+
+        let res = dsl::report_comments
+            .filter(dsl::report_id.eq(path_ids.0))
+            .filter(dsl::id.eq(path_ids.1))
+            .first(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    /// Replace the body of a comment, which only its author may do
+    #[instrument(name = "ReportComment::update_body", skip_all, err, fields(db.rows))]
+    pub fn update_body(
+        path_ids: (i64, i64),
+        author_id: i64,
+        body: &str,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
+        use crate::schema::report_comments::dsl;
+
+        recorded(conn.transaction(|conn| {
+            let before = Self::get_by_path(path_ids, conn)?;
+            ensure!(
+                before.author_id == author_id,
+                "Comment can only be edited by its author"
+            );
+
+            let res: Self = diesel::update(
+                dsl::report_comments
+                    .filter(dsl::report_id.eq(path_ids.0))
+                    .filter(dsl::id.eq(path_ids.1)),
+            )
+            .set((
+                dsl::body.eq(body),
+                dsl::updated_at.eq(Some(chrono::Utc::now())),
+            ))
+            .get_result(conn)?;
+            AuditLog::record(
+                AuditAction::Update,
+                Some(&before),
+                Some(&res),
+                Some(author_id),
+                conn,
+            )?;
+
+            Ok(res)
+        }))
+    }
+
+    /// Move the comments on a line item along with it to another report
+    ///
+    /// Should be called inside the transaction moving the item.
+    #[instrument(name = "ReportComment::move_with_line_item", skip_all, err, fields(db.rows))]
+    pub(crate) fn move_with_line_item(
+        path_ids: (i64, i64),
+        to_report_id: i64,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<Self>> {
+        use crate::schema::report_comments::dsl;
+
+        let before = Self::get_by_line_item(path_ids, conn)?;
+        let moved: Vec<Self> = diesel::update(
+            dsl::report_comments
+                .filter(dsl::report_id.eq(path_ids.0))
+                .filter(dsl::line_item_id.eq(path_ids.1)),
+        )
+        .set(dsl::report_id.eq(to_report_id))
+        .get_results(conn)?;
+        for after in &moved {
+            let before = before.iter().find(|comment| comment.id == after.id);
+            AuditLog::record(AuditAction::Update, before, Some(after), actor_id, conn)?;
+        }
+
+        recorded(Ok(moved))
+    }
+
+    /// Delete a comment, which only its author may do
+    #[instrument(name = "ReportComment::delete", skip_all, err, fields(db.rows))]
+    pub fn delete(path_ids: (i64, i64), author_id: i64, conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::report_comments::dsl;
+
+        recorded(conn.transaction(|conn| {
+            let res: Self = diesel::delete(
+                dsl::report_comments
+                    .filter(dsl::report_id.eq(path_ids.0))
+                    .filter(dsl::id.eq(path_ids.1))
+                    .filter(dsl::author_id.eq(author_id)),
+            )
+            .get_result(conn)?;
+            AuditLog::record(AuditAction::Delete, Some(&res), None, Some(author_id), conn)?;
+
+            Ok(res)
+        }))
+    }
+}
@@ -1,10 +1,18 @@
 #![allow(dead_code)]
 
+use super::audit_log::AuditAction;
+use super::instrumentation::recorded;
+use super::merge_patch;
+use super::row_version;
 use super::traits::*;
-use super::{NewReportLineItem, Report, ReportLineItem};
+use super::{
+    AuditLog, NewReportLineItem, Report, ReportComment, ReportLineItem, ReportLineItemPatch,
+};
 use anyhow::Result;
 use diesel::prelude::*;
 use diesel::PgConnection;
+use serde_json::Value;
+use tracing::instrument;
 
 #[derive(Default, Debug)]
 pub struct NewReportLineItemBuilder {
@@ -50,53 +58,136 @@ impl NewReportLineItemBuilder {
     pub fn item_price_usd(&mut self, price_usd: f64) -> &mut Self {
         use diesel::data_types::Cents;
 
-        let price_usd_cents = Cents((price_usd * 100.0).trunc() as i64);
+        let price_usd_cents = Cents((price_usd * 100.0).round() as i64);
 
         self.item_price_usd = Some(price_usd_cents);
         self
     }
 }
 
+/// Change to one line item of a report, as part of a bulk request
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineItemOperation {
+    Create {
+        item_name: String,
+        item_price_usd: diesel::data_types::Cents,
+    },
+    Update {
+        id: i64,
+        item_name: String,
+        item_price_usd: diesel::data_types::Cents,
+        expected_version: Option<i32>,
+    },
+    Delete {
+        id: i64,
+        expected_version: Option<i32>,
+    },
+}
+
+/// A bulk request was rolled back because one of its operations failed
+#[derive(Debug)]
+pub struct BulkOperationFailed {
+    /// Position of the failed operation in the request
+    pub index: usize,
+    pub error: anyhow::Error,
+}
+
+impl std::fmt::Display for BulkOperationFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Operation {} failed: {}", self.index, self.error)
+    }
+}
+
+impl std::error::Error for BulkOperationFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
 impl HasBuilder<NewReportLineItemBuilder, Self> for NewReportLineItem {}
 impl NewReportLineItem {
-    pub fn insert(&self, conn: &mut PgConnection) -> Result<ReportLineItem> {
+    #[instrument(name = "NewReportLineItem::insert", skip_all, err, fields(db.rows))]
+    pub fn insert(&self, actor_id: Option<i64>, conn: &mut PgConnection) -> Result<ReportLineItem> {
         use crate::schema::report_line_items::dsl;
 
-        let res = diesel::insert_into(dsl::report_line_items)
-            .values(self)
-            .get_result(conn)?;
+        recorded(conn.transaction(|conn| {
+            let res: ReportLineItem = diesel::insert_into(dsl::report_line_items)
+                .values(self)
+                .get_result(conn)?;
+            AuditLog::record(AuditAction::Create, None, Some(&res), actor_id, conn)?;
+
+            Ok(res)
+        }))
+    }
+}
+
+impl Audited for ReportLineItem {
+    const ENTITY: &'static str = "report_line_item";
+
+    fn entity_id(&self) -> i64 {
+        self.id
+    }
+
+    fn audit_report_id(&self) -> Option<i64> {
+        Some(self.report_id)
+    }
 
-        Ok(res)
+    fn audit_value(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "report_id": self.report_id,
+            "item_name": self.item_name,
+            "item_price_usd_cents": self.item_price_usd.0,
+        })
     }
 }
 
 impl HasBuilder<NewReportLineItemBuilder, NewReportLineItem> for ReportLineItem {}
 impl ReportLineItem {
-    pub fn clear(conn: &mut PgConnection) -> Result<()> {
+    #[instrument(name = "ReportLineItem::clear", skip_all, err)]
+    pub fn clear(actor_id: Option<i64>, conn: &mut PgConnection) -> Result<()> {
         use crate::schema::report_line_items::dsl;
 
-        diesel::delete(dsl::report_line_items).execute(conn)?;
+        conn.transaction(|conn| {
+            let deleted: Vec<Self> = diesel::delete(dsl::report_line_items).get_results(conn)?;
+            for item in &deleted {
+                AuditLog::record(AuditAction::Delete, Some(item), None, actor_id, conn)?;
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
-    pub fn clear_by_report(report_id: i64, conn: &mut PgConnection) -> Result<()> {
+    #[instrument(name = "ReportLineItem::clear_by_report", skip_all, err)]
+    pub fn clear_by_report(
+        report_id: i64,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<()> {
         use crate::schema::report_line_items::dsl;
 
-        diesel::delete(dsl::report_line_items.filter(dsl::report_id.eq(report_id)))
-            .execute(conn)?;
+        conn.transaction(|conn| {
+            let deleted: Vec<Self> =
+                diesel::delete(dsl::report_line_items.filter(dsl::report_id.eq(report_id)))
+                    .get_results(conn)?;
+            for item in &deleted {
+                AuditLog::record(AuditAction::Delete, Some(item), None, actor_id, conn)?;
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
+    #[instrument(name = "ReportLineItem::get_by_id", skip_all, err, fields(db.rows))]
     pub fn get_by_id(id: i64, conn: &mut PgConnection) -> Result<Self> {
         use crate::schema::report_line_items::dsl;
 
         let res = dsl::report_line_items.filter(dsl::id.eq(id)).first(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "ReportLineItem::get_by_report", skip_all, err, fields(db.rows))]
     pub fn get_by_report(report_id: i64, conn: &mut PgConnection) -> Result<Vec<Self>> {
         use crate::schema::report_line_items::dsl;
 
@@ -105,9 +196,10 @@ impl ReportLineItem {
             .select(Self::as_select())
             .load(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "ReportLineItem::get_by_path", skip_all, err, fields(db.rows))]
     pub fn get_by_path(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
         use crate::schema::report_line_items::dsl;
 
@@ -116,70 +208,321 @@ impl ReportLineItem {
             .filter(dsl::id.eq(path_ids.1))
             .first(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
+    }
+
+    /// Like [`ReportLineItem::get_by_path`], but holds a row lock until the end of the transaction
+    #[instrument(name = "ReportLineItem::lock_by_path", skip_all, err, fields(db.rows))]
+    fn lock_by_path(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::report_line_items::dsl;
+
+        let res = dsl::report_line_items
+            .filter(dsl::report_id.eq(path_ids.0))
+            .filter(dsl::id.eq(path_ids.1))
+            .for_update()
+            .first(conn)?;
+
+        recorded(Ok(res))
     }
 
-    pub fn delete(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
+    #[instrument(name = "ReportLineItem::delete", skip_all, err, fields(db.rows))]
+    pub fn delete(
+        path_ids: (i64, i64),
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
         use crate::schema::report_line_items::dsl;
 
-        let res = diesel::delete(
-            dsl::report_line_items
-                .filter(dsl::report_id.eq(path_ids.0))
-                .filter(dsl::id.eq(path_ids.1)),
-        )
-        .get_result(conn)?;
+        recorded(conn.transaction(|conn| {
+            row_version::check(
+                Self::lock_by_path(path_ids, conn)?.version,
+                expected_version,
+            )?;
+            let res: Self = diesel::delete(
+                dsl::report_line_items
+                    .filter(dsl::report_id.eq(path_ids.0))
+                    .filter(dsl::id.eq(path_ids.1)),
+            )
+            .get_result(conn)?;
+            AuditLog::record(AuditAction::Delete, Some(&res), None, actor_id, conn)?;
 
-        Ok(res)
+            Ok(res)
+        }))
     }
 
+    #[instrument(name = "ReportLineItem::update_using_cents", skip_all, err, fields(db.rows))]
     fn update_using_cents(
         path_ids: (i64, i64),
         report_id: i64,
         name: &str,
         price_usd: diesel::data_types::Cents,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<Self> {
         use crate::schema::report_line_items::dsl;
 
-        let res = diesel::update(
-            dsl::report_line_items
-                .filter(dsl::report_id.eq(path_ids.0))
-                .filter(dsl::id.eq(path_ids.1)),
-        )
-        .set((
-            dsl::report_id.eq(report_id),
-            dsl::item_name.eq(name),
-            dsl::item_price_usd.eq(price_usd),
-        ))
-        .get_result(conn)?;
-
-        Ok(res)
+        recorded(conn.transaction(|conn| {
+            let before = Self::lock_by_path(path_ids, conn)?;
+            row_version::check(before.version, expected_version)?;
+            let res: Self = diesel::update(
+                dsl::report_line_items
+                    .filter(dsl::report_id.eq(path_ids.0))
+                    .filter(dsl::id.eq(path_ids.1)),
+            )
+            .set((
+                dsl::report_id.eq(report_id),
+                dsl::item_name.eq(name),
+                dsl::item_price_usd.eq(price_usd),
+            ))
+            .get_result(conn)?;
+            AuditLog::record(
+                AuditAction::Update,
+                Some(&before),
+                Some(&res),
+                actor_id,
+                conn,
+            )?;
+
+            Ok(res)
+        }))
     }
 
+    #[instrument(name = "ReportLineItem::update", skip_all, err, fields(db.rows))]
     pub fn update(
         path_ids: (i64, i64),
         report_id: i64,
         name: &str,
         price_usd: f64,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<Self> {
         use diesel::data_types::Cents;
-        let price_usd_cents = Cents((price_usd * 100.0).trunc() as i64);
+        let price_usd_cents = Cents((price_usd * 100.0).round() as i64);
 
-        Self::update_using_cents(path_ids, report_id, name, price_usd_cents, conn)
+        recorded(Self::update_using_cents(
+            path_ids,
+            report_id,
+            name,
+            price_usd_cents,
+            expected_version,
+            actor_id,
+            conn,
+        ))
     }
 
+    #[instrument(name = "ReportLineItem::replace", skip_all, err, fields(db.rows))]
     pub fn replace(
         path_ids: (i64, i64),
         new: &NewReportLineItem,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<Self> {
-        Self::update_using_cents(
+        recorded(Self::update_using_cents(
             path_ids,
             new.report_id,
             &new.item_name,
             new.item_price_usd,
+            expected_version,
+            actor_id,
             conn,
-        )
+        ))
+    }
+
+    /// Apply a JSON Merge Patch to the name and price of a line item
+    #[instrument(name = "ReportLineItem::patch", skip_all, err, fields(db.rows))]
+    pub fn patch(
+        path_ids: (i64, i64),
+        patch: &Value,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
+        recorded(conn.transaction(|conn| {
+            let current = Self::lock_by_path(path_ids, conn)?;
+            row_version::check(current.version, expected_version)?;
+
+            let (name, price_usd) =
+                patched_fields(current.item_name, current.item_price_usd, patch)?;
+
+            Self::update_using_cents(
+                path_ids,
+                current.report_id,
+                &name,
+                price_usd,
+                None,
+                actor_id,
+                conn,
+            )
+        }))
+    }
+
+    /// Apply operations to the line items of a report in order, all or nothing
+    ///
+    /// Returns the created, updated or deleted item for each operation.
+    #[instrument(name = "ReportLineItem::apply_bulk", skip_all, err, fields(db.rows))]
+    pub fn apply_bulk(
+        report_id: i64,
+        operations: &[LineItemOperation],
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<Self>> {
+        recorded(conn.transaction(|conn| {
+            operations
+                .iter()
+                .enumerate()
+                .map(|(index, operation)| {
+                    Self::apply_operation(report_id, operation, actor_id, conn)
+                        .map_err(|error| BulkOperationFailed { index, error }.into())
+                })
+                .collect()
+        }))
+    }
+
+    #[instrument(name = "ReportLineItem::apply_operation", skip_all, err, fields(db.rows))]
+    fn apply_operation(
+        report_id: i64,
+        operation: &LineItemOperation,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
+        recorded(match operation {
+            LineItemOperation::Create {
+                item_name,
+                item_price_usd,
+            } => NewReportLineItem {
+                report_id,
+                item_name: item_name.clone(),
+                item_price_usd: *item_price_usd,
+            }
+            .insert(actor_id, conn),
+            LineItemOperation::Update {
+                id,
+                item_name,
+                item_price_usd,
+                expected_version,
+            } => Self::update_using_cents(
+                (report_id, *id),
+                report_id,
+                item_name,
+                *item_price_usd,
+                *expected_version,
+                actor_id,
+                conn,
+            ),
+            LineItemOperation::Delete {
+                id,
+                expected_version,
+            } => Self::delete((report_id, *id), *expected_version, actor_id, conn),
+        })
+    }
+
+    /// Move line items of one report to another, all or nothing
+    ///
+    /// Each item is logged as deleted from its old report and updated in the new one,
+    /// so both reports' history shows the move. Comments on the items move with them.
+    #[instrument(name = "ReportLineItem::move_to_report", skip_all, err, fields(db.rows))]
+    pub fn move_to_report(
+        from_report_id: i64,
+        item_ids: &[i64],
+        to_report_id: i64,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<Self>> {
+        recorded(conn.transaction(|conn| {
+            item_ids
+                .iter()
+                .enumerate()
+                .map(|(index, id)| {
+                    let path_ids = (from_report_id, *id);
+                    Self::lock_by_path(path_ids, conn)
+                        .and_then(|item| {
+                            AuditLog::record(
+                                AuditAction::Delete,
+                                Some(&item),
+                                None,
+                                actor_id,
+                                conn,
+                            )?;
+                            let moved = Self::update_using_cents(
+                                path_ids,
+                                to_report_id,
+                                &item.item_name,
+                                item.item_price_usd,
+                                None,
+                                actor_id,
+                                conn,
+                            )?;
+                            ReportComment::move_with_line_item(
+                                path_ids,
+                                to_report_id,
+                                actor_id,
+                                conn,
+                            )?;
+
+                            Ok(moved)
+                        })
+                        .map_err(|error| BulkOperationFailed { index, error }.into())
+                })
+                .collect()
+        }))
+    }
+}
+
+/// Merge `patch` into a line item's name and price
+///
+/// The price is only converted back from dollars when the patch sets it, since
+/// cents do not survive a round trip through `f64` unchanged.
+fn patched_fields(
+    name: String,
+    price_usd: diesel::data_types::Cents,
+    patch: &Value,
+) -> Result<(String, diesel::data_types::Cents)> {
+    use diesel::data_types::Cents;
+
+    let fields = ReportLineItemPatch {
+        item_name: name,
+        item_price_usd: price_usd.0 as f64 / 100.0,
+    };
+    let changes = merge_patch::apply(&fields, patch)?;
+    let price_usd = match patch.get("item_price_usd") {
+        Some(_) => Cents((changes.item_price_usd * 100.0).round() as i64),
+        None => price_usd,
+    };
+
+    Ok((changes.item_name, price_usd))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::data_types::Cents;
+    use serde_json::json;
+
+    #[test]
+    fn renaming_keeps_the_price() {
+        for cents in 0..10_000 {
+            let (name, price_usd) =
+                patched_fields("old".to_owned(), Cents(cents), &json!({"item_name": "new"}))
+                    .unwrap();
+            assert_eq!(name, "new");
+            assert_eq!(price_usd, Cents(cents));
+        }
+    }
+
+    #[test]
+    fn patched_price_is_rounded_to_cents() {
+        for (dollars, cents) in [(0.29, 29), (0.57, 57), (1.15, 115), (19.99, 1999)] {
+            let (_, price_usd) = patched_fields(
+                "item".to_owned(),
+                Cents(0),
+                &json!({"item_price_usd": dollars}),
+            )
+            .unwrap();
+            assert_eq!(price_usd, Cents(cents), "{dollars}");
+        }
     }
 }
@@ -1,10 +1,14 @@
 #![allow(dead_code)]
 
+use super::audit_log::{digest, AuditAction};
+use super::instrumentation::recorded;
+use super::row_version;
 use super::traits::*;
-use super::{NewReportProof, Report, ReportProof};
+use super::{AuditLog, NewReportProof, Report, ReportProof};
 use anyhow::Result;
 use diesel::prelude::*;
 use diesel::PgConnection;
+use tracing::instrument;
 
 #[derive(Default, Debug)]
 pub struct NewReportProofBuilder {
@@ -43,43 +47,87 @@ impl NewReportProofBuilder {
 
 impl HasBuilder<NewReportProofBuilder, Self> for NewReportProof {}
 impl NewReportProof {
-    pub fn insert(&self, conn: &mut PgConnection) -> Result<ReportProof> {
+    #[instrument(name = "NewReportProof::insert", skip_all, err, fields(db.rows))]
+    pub fn insert(&self, actor_id: Option<i64>, conn: &mut PgConnection) -> Result<ReportProof> {
         use crate::schema::report_proof::dsl;
 
-        let res = diesel::insert_into(dsl::report_proof)
-            .values(self)
-            .get_result(conn)?;
+        recorded(conn.transaction(|conn| {
+            let res: ReportProof = diesel::insert_into(dsl::report_proof)
+                .values(self)
+                .get_result(conn)?;
+            AuditLog::record(AuditAction::Create, None, Some(&res), actor_id, conn)?;
 
-        Ok(res)
+            Ok(res)
+        }))
+    }
+}
+
+impl Audited for ReportProof {
+    const ENTITY: &'static str = "report_proof";
+
+    fn entity_id(&self) -> i64 {
+        self.id
+    }
+
+    fn audit_report_id(&self) -> Option<i64> {
+        Some(self.report_id)
+    }
+
+    fn audit_value(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "report_id": self.report_id,
+            "data_sha256": digest(&self.data),
+        })
     }
 }
 
 impl HasBuilder<NewReportProofBuilder, NewReportProof> for ReportProof {}
 impl ReportProof {
-    pub fn clear(conn: &mut PgConnection) -> Result<()> {
+    #[instrument(name = "ReportProof::clear", skip_all, err)]
+    pub fn clear(actor_id: Option<i64>, conn: &mut PgConnection) -> Result<()> {
         use crate::schema::report_proof::dsl;
 
-        diesel::delete(dsl::report_proof).execute(conn)?;
+        conn.transaction(|conn| {
+            let deleted: Vec<Self> = diesel::delete(dsl::report_proof).get_results(conn)?;
+            for proof in &deleted {
+                AuditLog::record(AuditAction::Delete, Some(proof), None, actor_id, conn)?;
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
-    pub fn clear_by_report(report_id: i64, conn: &mut PgConnection) -> Result<()> {
+    #[instrument(name = "ReportProof::clear_by_report", skip_all, err)]
+    pub fn clear_by_report(
+        report_id: i64,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<()> {
         use crate::schema::report_proof::dsl;
 
-        diesel::delete(dsl::report_proof.filter(dsl::report_id.eq(report_id))).execute(conn)?;
+        conn.transaction(|conn| {
+            let deleted: Vec<Self> =
+                diesel::delete(dsl::report_proof.filter(dsl::report_id.eq(report_id)))
+                    .get_results(conn)?;
+            for proof in &deleted {
+                AuditLog::record(AuditAction::Delete, Some(proof), None, actor_id, conn)?;
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
+    #[instrument(name = "ReportProof::get_by_id", skip_all, err, fields(db.rows))]
     pub fn get_by_id(id: i64, conn: &mut PgConnection) -> Result<Self> {
         use crate::schema::report_proof::dsl;
 
         let res = dsl::report_proof.filter(dsl::id.eq(id)).first(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "ReportProof::get_by_report", skip_all, err, fields(db.rows))]
     pub fn get_by_report(report_id: i64, conn: &mut PgConnection) -> Result<Vec<Self>> {
         use crate::schema::report_proof::dsl;
 
@@ -88,9 +136,10 @@ impl ReportProof {
             .select(Self::as_select())
             .load(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "ReportProof::get_by_path", skip_all, err, fields(db.rows))]
     pub fn get_by_path(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
         use crate::schema::report_proof::dsl;
 
@@ -99,46 +148,97 @@ impl ReportProof {
             .filter(dsl::id.eq(path_ids.1))
             .first(conn)?;
 
-        Ok(res)
+        recorded(Ok(res))
+    }
+
+    /// Like [`ReportProof::get_by_path`], but holds a row lock until the end of the transaction
+    #[instrument(name = "ReportProof::lock_by_path", skip_all, err, fields(db.rows))]
+    fn lock_by_path(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::report_proof::dsl;
+
+        let res = dsl::report_proof
+            .filter(dsl::report_id.eq(path_ids.0))
+            .filter(dsl::id.eq(path_ids.1))
+            .for_update()
+            .first(conn)?;
+
+        recorded(Ok(res))
     }
 
-    pub fn delete(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
+    #[instrument(name = "ReportProof::delete", skip_all, err, fields(db.rows))]
+    pub fn delete(
+        path_ids: (i64, i64),
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
         use crate::schema::report_proof::dsl;
 
-        let res = diesel::delete(
-            dsl::report_proof
-                .filter(dsl::report_id.eq(path_ids.0))
-                .filter(dsl::id.eq(path_ids.1)),
-        )
-        .get_result(conn)?;
+        recorded(conn.transaction(|conn| {
+            row_version::check(
+                Self::lock_by_path(path_ids, conn)?.version,
+                expected_version,
+            )?;
+            let res: Self = diesel::delete(
+                dsl::report_proof
+                    .filter(dsl::report_id.eq(path_ids.0))
+                    .filter(dsl::id.eq(path_ids.1)),
+            )
+            .get_result(conn)?;
+            AuditLog::record(AuditAction::Delete, Some(&res), None, actor_id, conn)?;
 
-        Ok(res)
+            Ok(res)
+        }))
     }
 
+    #[instrument(name = "ReportProof::update", skip_all, err, fields(db.rows))]
     pub fn update(
         path_ids: (i64, i64),
         report_id: i64,
         data: &[u8],
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<Self> {
         use crate::schema::report_proof::dsl;
 
-        let res = diesel::update(
-            dsl::report_proof
-                .filter(dsl::report_id.eq(path_ids.0))
-                .filter(dsl::id.eq(path_ids.1)),
-        )
-        .set((dsl::report_id.eq(report_id), dsl::data.eq(data)))
-        .get_result(conn)?;
-
-        Ok(res)
+        recorded(conn.transaction(|conn| {
+            let before = Self::lock_by_path(path_ids, conn)?;
+            row_version::check(before.version, expected_version)?;
+            let res: Self = diesel::update(
+                dsl::report_proof
+                    .filter(dsl::report_id.eq(path_ids.0))
+                    .filter(dsl::id.eq(path_ids.1)),
+            )
+            .set((dsl::report_id.eq(report_id), dsl::data.eq(data)))
+            .get_result(conn)?;
+            AuditLog::record(
+                AuditAction::Update,
+                Some(&before),
+                Some(&res),
+                actor_id,
+                conn,
+            )?;
+
+            Ok(res)
+        }))
     }
 
+    #[instrument(name = "ReportProof::replace", skip_all, err, fields(db.rows))]
     pub fn replace(
         path_ids: (i64, i64),
         new: &NewReportProof,
+        expected_version: Option<i32>,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<Self> {
-        Self::update(path_ids, new.report_id, &new.data, conn)
+        recorded(Self::update(
+            path_ids,
+            new.report_id,
+            &new.data,
+            expected_version,
+            actor_id,
+            conn,
+        ))
     }
 }
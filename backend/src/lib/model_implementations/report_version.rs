@@ -0,0 +1,290 @@
+#![allow(dead_code)]
+
+use super::audit_log::{diff, digest};
+use super::instrumentation::recorded;
+use super::{
+    NewReportLineItem, NewReportVersion, Report, ReportLineItem, ReportProof, ReportVersion,
+};
+use anyhow::{Context, Result};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+/// Reason recorded for snapshots requested through the API
+pub const MANUAL_SNAPSHOT: &str = "manual";
+/// Reason recorded for the snapshot taken of the current state before a restore
+pub const BEFORE_RESTORE_SNAPSHOT: &str = "before_restore";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct LineItemSnapshot {
+    pub id: i64,
+    pub item_name: String,
+    pub item_price_usd_cents: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ProofSnapshot {
+    pub id: i64,
+    pub data_sha256: String,
+    pub size: usize,
+}
+
+/// State of a report with its line items and proof metadata at a point in time
+///
+/// Proof data itself is not copied, only enough to tell proofs apart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReportSnapshot {
+    pub owner_id: i64,
+    pub title: String,
+    pub description: Option<String>,
+    pub items: Vec<LineItemSnapshot>,
+    pub proof: Vec<ProofSnapshot>,
+}
+
+#[derive(Serialize, Debug, PartialEq, ToSchema)]
+pub struct LineItemChange {
+    pub id: i64,
+    #[schema(value_type = Object)]
+    pub changes: serde_json::Value,
+}
+
+/// Differences between two versions of a report
+#[derive(Serialize, Debug, PartialEq, ToSchema)]
+pub struct ReportVersionDiff {
+    pub from: i32,
+    pub to: i32,
+    /// Changed report fields, each as an object with `before` and `after` values
+    #[schema(value_type = Object)]
+    pub report: serde_json::Value,
+    pub items_added: Vec<LineItemSnapshot>,
+    pub items_removed: Vec<LineItemSnapshot>,
+    pub items_changed: Vec<LineItemChange>,
+    pub proof_added: Vec<ProofSnapshot>,
+    pub proof_removed: Vec<ProofSnapshot>,
+}
+
+impl ReportSnapshot {
+    #[instrument(name = "ReportSnapshot::capture", skip_all, err, fields(db.rows))]
+    pub fn capture(report_id: i64, conn: &mut PgConnection) -> Result<Self> {
+        let report = Report::get_by_id(report_id, conn)?;
+        let items = ReportLineItem::get_by_report(report_id, conn)?
+            .into_iter()
+            .map(|item| LineItemSnapshot {
+                id: item.id,
+                item_name: item.item_name,
+                item_price_usd_cents: item.item_price_usd.0,
+            })
+            .collect();
+        let proof = ReportProof::get_by_report(report_id, conn)?
+            .into_iter()
+            .map(|proof| ProofSnapshot {
+                id: proof.id,
+                data_sha256: digest(&proof.data),
+                size: proof.data.len(),
+            })
+            .collect();
+
+        recorded(Ok(Self {
+            owner_id: report.owner_id,
+            title: report.title,
+            description: report.description,
+            items,
+            proof,
+        }))
+    }
+
+    fn fields(&self) -> serde_json::Value {
+        serde_json::json!({
+            "owner_id": self.owner_id,
+            "title": self.title,
+            "description": self.description,
+        })
+    }
+}
+
+impl ReportVersion {
+    #[instrument(name = "ReportVersion::get_by_report", skip_all, err, fields(db.rows))]
+    pub fn get_by_report(report_id: i64, conn: &mut PgConnection) -> Result<Vec<Self>> {
+        use crate::schema::report_versions::dsl;
+
+        let res = dsl::report_versions
+            .filter(dsl::report_id.eq(report_id))
+            .order(dsl::version.asc())
+            .select(Self::as_select())
+            .load(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "ReportVersion::get_by_version", skip_all, err, fields(db.rows))]
+    pub fn get_by_version(report_id: i64, version: i32, conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::report_versions::dsl;
+
+        let res = dsl::report_versions
+            .filter(dsl::report_id.eq(report_id))
+            .filter(dsl::version.eq(version))
+            .first(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    pub fn parse_snapshot(&self) -> Result<ReportSnapshot> {
+        serde_json::from_value(self.snapshot.clone()).context("Malformed report snapshot")
+    }
+
+    /// Store the current state of a report as its next version
+    ///
+    /// The report row is locked first, so concurrent snapshots get consecutive versions.
+    #[instrument(name = "ReportVersion::snapshot", skip_all, err, fields(db.rows))]
+    pub fn snapshot(
+        report_id: i64,
+        reason: &str,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
+        use crate::schema::report_versions::dsl;
+
+        recorded(conn.transaction(|conn| {
+            Report::lock_by_id(report_id, conn)?;
+            let snapshot = ReportSnapshot::capture(report_id, conn)?;
+            let latest: Option<i32> = dsl::report_versions
+                .filter(dsl::report_id.eq(report_id))
+                .select(diesel::dsl::max(dsl::version))
+                .first(conn)?;
+
+            let new = NewReportVersion {
+                report_id,
+                version: latest.unwrap_or(0) + 1,
+                actor_id,
+                reason: reason.to_owned(),
+                snapshot: serde_json::to_value(snapshot)?,
+            };
+            let res = diesel::insert_into(dsl::report_versions)
+                .values(&new)
+                .get_result(conn)?;
+
+            Ok(res)
+        }))
+    }
+
+    #[instrument(name = "ReportVersion::diff", skip_all, err, fields(db.rows))]
+    pub fn diff(
+        report_id: i64,
+        from: i32,
+        to: i32,
+        conn: &mut PgConnection,
+    ) -> Result<ReportVersionDiff> {
+        let old = Self::get_by_version(report_id, from, conn)?.parse_snapshot()?;
+        let new = Self::get_by_version(report_id, to, conn)?.parse_snapshot()?;
+
+        let items_added = new
+            .items
+            .iter()
+            .filter(|item| !old.items.iter().any(|o| o.id == item.id))
+            .cloned()
+            .collect();
+        let items_removed = old
+            .items
+            .iter()
+            .filter(|item| !new.items.iter().any(|n| n.id == item.id))
+            .cloned()
+            .collect();
+        let items_changed = new
+            .items
+            .iter()
+            .filter_map(|item| {
+                let previous = old.items.iter().find(|o| o.id == item.id)?;
+                if previous == item {
+                    return None;
+                }
+
+                Some(LineItemChange {
+                    id: item.id,
+                    changes: diff(
+                        serde_json::to_value(previous).ok(),
+                        serde_json::to_value(item).ok(),
+                        &[],
+                    ),
+                })
+            })
+            .collect();
+        let proof_added = new
+            .proof
+            .iter()
+            .filter(|proof| !old.proof.contains(proof))
+            .cloned()
+            .collect();
+        let proof_removed = old
+            .proof
+            .iter()
+            .filter(|proof| !new.proof.contains(proof))
+            .cloned()
+            .collect();
+
+        recorded(Ok(ReportVersionDiff {
+            from,
+            to,
+            report: diff(Some(old.fields()), Some(new.fields()), &[]),
+            items_added,
+            items_removed,
+            items_changed,
+            proof_added,
+            proof_removed,
+        }))
+    }
+
+    /// Return a report's title, description and line items to those of an earlier version
+    ///
+    /// The current state is snapshotted first so the restore can itself be undone.
+    /// Ownership and proof are left as they are, since proof data is not part of snapshots.
+    /// Items still on the report are updated in place, keeping their ids and comments;
+    /// only items missing from one side are inserted or deleted.
+    #[instrument(name = "ReportVersion::restore", skip_all, err, fields(db.rows))]
+    pub fn restore(
+        report_id: i64,
+        version: i32,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Report> {
+        recorded(conn.transaction(|conn| {
+            let target = Self::get_by_version(report_id, version, conn)?.parse_snapshot()?;
+            let current = Self::snapshot(report_id, BEFORE_RESTORE_SNAPSHOT, actor_id, conn)?
+                .parse_snapshot()?;
+
+            let res = Report::update(
+                report_id,
+                current.owner_id,
+                target.title,
+                target.description,
+                None,
+                actor_id,
+                conn,
+            )?;
+            for item in &current.items {
+                if !target.items.iter().any(|t| t.id == item.id) {
+                    ReportLineItem::delete((report_id, item.id), None, actor_id, conn)?;
+                }
+            }
+            for item in target.items {
+                let new = NewReportLineItem {
+                    report_id,
+                    item_name: item.item_name.clone(),
+                    item_price_usd: diesel::data_types::Cents(item.item_price_usd_cents),
+                };
+                match current.items.iter().find(|c| c.id == item.id) {
+                    Some(existing) if *existing == item => {}
+                    Some(_) => {
+                        ReportLineItem::replace((report_id, item.id), &new, None, actor_id, conn)?;
+                    }
+                    None => {
+                        new.insert(actor_id, conn)?;
+                    }
+                }
+            }
+
+            Ok(res)
+        }))
+    }
+}
@@ -0,0 +1,31 @@
+use anyhow::Result;
+
+/// The row was changed after the client last read it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VersionConflict {
+    pub expected: i32,
+    pub current: i32,
+}
+
+impl std::fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Expected version {} but the row is at version {}",
+            self.expected, self.current
+        )
+    }
+}
+
+impl std::error::Error for VersionConflict {}
+
+/// Fail with [`VersionConflict`] unless the row is still at the version the client expected
+///
+/// `current` should be read with a row lock in the transaction making the change,
+/// so the version cannot move before the change is written.
+pub(crate) fn check(current: i32, expected: Option<i32>) -> Result<()> {
+    match expected {
+        Some(expected) if expected != current => Err(VersionConflict { expected, current }.into()),
+        _ => Ok(()),
+    }
+}
@@ -1,11 +1,17 @@
 #![allow(dead_code)]
 
+use super::audit_log::{digest, AuditAction};
+use super::instrumentation::recorded;
+use super::merge_patch;
 use super::traits::*;
-use super::{NewUser, User};
+use super::{AuditLog, NewUser, User, UserPatch};
 use anyhow::Result;
 use diesel::prelude::*;
 use diesel::PgConnection;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::instrument;
+use utoipa::ToSchema;
 
 fn hash_password(_password: &str) -> String {
     todo!()
@@ -19,7 +25,7 @@ pub struct NewUserBuilder {
     password_hash: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UserInfo {
     username: String,
     email: String,
@@ -78,27 +84,87 @@ impl NewUserBuilder {
 
 impl HasBuilder<NewUserBuilder, Self> for NewUser {}
 impl NewUser {
-    pub fn insert(&self, conn: &mut PgConnection) -> Result<UserInfo> {
+    #[instrument(name = "NewUser::insert", skip_all, err, fields(db.rows))]
+    pub fn insert(&self, actor_id: Option<i64>, conn: &mut PgConnection) -> Result<UserInfo> {
         use crate::schema::users::dsl;
 
-        let res = diesel::insert_into(dsl::users)
-            .values(self)
-            .get_result::<User>(conn)?;
+        recorded(conn.transaction(|conn| {
+            let res: User = diesel::insert_into(dsl::users)
+                .values(self)
+                .get_result(conn)?;
+            AuditLog::record(AuditAction::Create, None, Some(&res), actor_id, conn)?;
 
-        Ok(res.into())
+            Ok(res.into())
+        }))
+    }
+}
+
+impl Audited for User {
+    const ENTITY: &'static str = "user";
+    const REDACTED: &'static [&'static str] = &["password_hash"];
+
+    fn entity_id(&self) -> i64 {
+        self.id
+    }
+
+    fn audit_report_id(&self) -> Option<i64> {
+        None
+    }
+
+    fn audit_value(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "username": self.username,
+            "email": self.email,
+            "profile_picture_sha256": self.profile_picture.as_deref().map(digest),
+            "password_hash": self.password_hash,
+            "is_admin": self.is_admin,
+        })
     }
 }
 
 impl HasBuilder<NewUserBuilder, NewUser> for User {}
 impl User {
+    #[instrument(name = "User::get_by_id", skip_all, err, fields(db.rows))]
     pub fn get_by_id(id: i64, conn: &mut PgConnection) -> Result<UserInfo> {
         use crate::schema::users::dsl;
 
         let res = dsl::users.filter(dsl::id.eq(id)).first::<User>(conn)?;
 
-        Ok(res.into())
+        recorded(Ok(res.into()))
+    }
+
+    #[instrument(name = "User::count", skip_all, err, fields(db.rows))]
+    pub fn count(conn: &mut PgConnection) -> Result<i64> {
+        use crate::schema::users::dsl;
+
+        let res = dsl::users.count().get_result(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "User::get_full_by_id", skip_all, err, fields(db.rows))]
+    pub(crate) fn get_full_by_id(id: i64, conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::users::dsl;
+
+        let res = dsl::users.filter(dsl::id.eq(id)).first(conn)?;
+
+        recorded(Ok(res))
     }
 
+    #[instrument(name = "User::is_admin", skip_all, err, fields(db.rows))]
+    pub fn is_admin(id: i64, conn: &mut PgConnection) -> Result<bool> {
+        use crate::schema::users::dsl;
+
+        let res = dsl::users
+            .filter(dsl::id.eq(id))
+            .select(dsl::is_admin)
+            .first(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "User::get_profile_picture", skip_all, err, fields(db.rows))]
     pub fn get_profile_picture(id: i64, conn: &mut PgConnection) -> Result<axum::body::Bytes> {
         use crate::schema::users::dsl;
 
@@ -106,116 +172,213 @@ impl User {
         let profile_picture = res.profile_picture.unwrap_or_default();
         let bytes = axum::body::Bytes::copy_from_slice(&profile_picture);
 
-        Ok(bytes)
+        recorded(Ok(bytes))
     }
 
-    pub fn delete(id: i64, conn: &mut PgConnection) -> Result<UserInfo> {
+    #[instrument(name = "User::delete", skip_all, err, fields(db.rows))]
+    pub fn delete(id: i64, actor_id: Option<i64>, conn: &mut PgConnection) -> Result<UserInfo> {
         use crate::schema::users::dsl;
 
-        let res = diesel::delete(dsl::users.filter(dsl::id.eq(id))).get_result::<User>(conn)?;
+        recorded(conn.transaction(|conn| {
+            let res: User = diesel::delete(dsl::users.filter(dsl::id.eq(id))).get_result(conn)?;
+            AuditLog::record(AuditAction::Delete, Some(&res), None, actor_id, conn)?;
 
-        Ok(res.into())
+            Ok(res.into())
+        }))
     }
 
-    pub fn clear(conn: &mut PgConnection) -> Result<()> {
+    #[instrument(name = "User::clear", skip_all, err)]
+    pub fn clear(actor_id: Option<i64>, conn: &mut PgConnection) -> Result<()> {
         use crate::schema::users::dsl;
 
-        diesel::delete(dsl::users).execute(conn)?;
+        conn.transaction(|conn| {
+            let deleted: Vec<Self> = diesel::delete(dsl::users).get_results(conn)?;
+            for user in &deleted {
+                AuditLog::record(AuditAction::Delete, Some(user), None, actor_id, conn)?;
+            }
+
+            Ok(())
+        })
+    }
 
-        Ok(())
+    /// Run an update against a single user, recording the before and after state
+    #[instrument(name = "User::audited_update", skip_all, err, fields(db.rows))]
+    fn audited_update<F>(
+        id: i64,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+        update: F,
+    ) -> Result<Self>
+    where
+        F: FnOnce(&mut PgConnection) -> QueryResult<Self>,
+    {
+        recorded(conn.transaction(|conn| {
+            let before = Self::get_full_by_id(id, conn)?;
+            let res = update(conn)?;
+            AuditLog::record(
+                AuditAction::Update,
+                Some(&before),
+                Some(&res),
+                actor_id,
+                conn,
+            )?;
+
+            Ok(res)
+        }))
     }
 
+    #[instrument(name = "User::update_hash", skip_all, err, fields(db.rows))]
     fn update_hash(
         id: i64,
         username: String,
         email: String,
         profile_picture: Option<Vec<u8>>,
         password_hash: String,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<UserInfo> {
         use crate::schema::users::dsl;
 
-        let res = diesel::update(dsl::users.filter(dsl::id.eq(id)))
-            .set((
-                dsl::username.eq(username),
-                dsl::email.eq(email),
-                dsl::profile_picture.eq(profile_picture),
-                dsl::password_hash.eq(password_hash),
-            ))
-            .get_result::<User>(conn)?;
-
-        Ok(res.into())
+        let res = Self::audited_update(id, actor_id, conn, |conn| {
+            diesel::update(dsl::users.filter(dsl::id.eq(id)))
+                .set((
+                    dsl::username.eq(username),
+                    dsl::email.eq(email),
+                    dsl::profile_picture.eq(profile_picture),
+                    dsl::password_hash.eq(password_hash),
+                ))
+                .get_result(conn)
+        })?;
+
+        recorded(Ok(res.into()))
     }
 
+    #[instrument(name = "User::update_info", skip_all, err, fields(db.rows))]
     pub fn update_info(
         id: i64,
         username: &str,
         email: &str,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<UserInfo> {
         use crate::schema::users::dsl;
 
-        let res = diesel::update(dsl::users.filter(dsl::id.eq(id)))
-            .set((dsl::username.eq(username), dsl::email.eq(email)))
-            .get_result::<User>(conn)?;
+        let res = Self::audited_update(id, actor_id, conn, |conn| {
+            diesel::update(dsl::users.filter(dsl::id.eq(id)))
+                .set((dsl::username.eq(username), dsl::email.eq(email)))
+                .get_result(conn)
+        })?;
 
-        Ok(res.into())
+        recorded(Ok(res.into()))
     }
 
+    #[instrument(name = "User::update", skip_all, err, fields(db.rows))]
     pub fn update(
         id: i64,
         username: String,
         email: String,
         profile_picture: Option<Vec<u8>>,
         password: String,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<UserInfo> {
         let password_hash = hash_password(&password);
 
-        Self::update_hash(id, username, email, profile_picture, password_hash, conn)
+        recorded(Self::update_hash(
+            id,
+            username,
+            email,
+            profile_picture,
+            password_hash,
+            actor_id,
+            conn,
+        ))
     }
 
-    pub fn replace(id: i64, new: &NewUser, conn: &mut PgConnection) -> Result<UserInfo> {
-        Self::update_hash(
+    #[instrument(name = "User::replace", skip_all, err, fields(db.rows))]
+    pub fn replace(
+        id: i64,
+        new: &NewUser,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<UserInfo> {
+        recorded(Self::update_hash(
             id,
             new.username.clone(),
             new.email.clone(),
             new.profile_picture.clone(),
             new.password_hash.clone(),
+            actor_id,
             conn,
-        )
+        ))
     }
 
+    #[instrument(name = "User::update_profile_picture", skip_all, err, fields(db.rows))]
     pub fn update_profile_picture(
         id: i64,
         profile_picture: &[u8],
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<Self> {
         use crate::schema::users::dsl;
 
-        let res = diesel::update(dsl::users.filter(dsl::id.eq(id)))
-            .set(dsl::profile_picture.eq(Some(profile_picture)))
-            .get_result(conn)?;
-
-        Ok(res)
+        recorded(Self::audited_update(id, actor_id, conn, |conn| {
+            diesel::update(dsl::users.filter(dsl::id.eq(id)))
+                .set(dsl::profile_picture.eq(Some(profile_picture)))
+                .get_result(conn)
+        }))
     }
 
+    #[instrument(name = "User::update_password_hash", skip_all, err, fields(db.rows))]
     pub fn update_password_hash(
         id: i64,
         password_hash: &str,
+        actor_id: Option<i64>,
         conn: &mut PgConnection,
     ) -> Result<Self> {
         use crate::schema::users::dsl;
 
-        let res = diesel::update(dsl::users.filter(dsl::id.eq(id)))
-            .set(dsl::password_hash.eq(password_hash))
-            .get_result(conn)?;
-
-        Ok(res)
+        recorded(Self::audited_update(id, actor_id, conn, |conn| {
+            diesel::update(dsl::users.filter(dsl::id.eq(id)))
+                .set(dsl::password_hash.eq(password_hash))
+                .get_result(conn)
+        }))
     }
 
-    pub fn update_password(id: i64, password: String, conn: &mut PgConnection) -> Result<Self> {
+    #[instrument(name = "User::update_password", skip_all, err, fields(db.rows))]
+    pub fn update_password(
+        id: i64,
+        password: String,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
         let password_hash = hash_password(&password);
-        Self::update_password_hash(id, &password_hash, conn)
+        recorded(Self::update_password_hash(
+            id,
+            &password_hash,
+            actor_id,
+            conn,
+        ))
+    }
+
+    /// Apply a JSON Merge Patch to the username and email of a user
+    #[instrument(name = "User::patch", skip_all, err, fields(db.rows))]
+    pub fn patch(
+        id: i64,
+        patch: &Value,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<UserInfo> {
+        recorded(conn.transaction(|conn| {
+            let current = Self::get_full_by_id(id, conn)?;
+
+            let fields = UserPatch {
+                username: current.username,
+                email: current.email,
+            };
+            let changes = merge_patch::apply(&fields, patch)?;
+
+            Self::update_info(id, &changes.username, &changes.email, actor_id, conn)
+        }))
     }
 }
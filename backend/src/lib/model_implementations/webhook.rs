@@ -0,0 +1,288 @@
+#![allow(dead_code)]
+
+use super::audit_log::AuditAction;
+use super::instrumentation::recorded;
+use super::traits::*;
+use super::{AuditLog, NewWebhook, Webhook, WebhookDelivery};
+use anyhow::Result;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use tracing::instrument;
+
+/// Failed deliveries are retried with exponential backoff until this many attempts
+pub const MAX_WEBHOOK_ATTEMPTS: i32 = 8;
+const MIN_SECRET_LENGTH: usize = 16;
+
+impl NewWebhook {
+    pub(crate) fn default_active() -> bool {
+        true
+    }
+
+    /// Whether the url is http(s), the secret is long enough and no event filter is null
+    pub fn is_valid(&self) -> bool {
+        (self.url.starts_with("https://") || self.url.starts_with("http://"))
+            && self.secret.len() >= MIN_SECRET_LENGTH
+            && self.events.iter().all(Option::is_some)
+    }
+
+    #[instrument(name = "NewWebhook::insert", skip_all, err, fields(db.rows))]
+    pub fn insert(&self, actor_id: Option<i64>, conn: &mut PgConnection) -> Result<Webhook> {
+        use crate::schema::webhooks::dsl;
+
+        recorded(conn.transaction(|conn| {
+            let res = diesel::insert_into(dsl::webhooks)
+                .values((self, dsl::created_by.eq(actor_id)))
+                .get_result(conn)?;
+            AuditLog::record(AuditAction::Create, None, Some(&res), actor_id, conn)?;
+
+            Ok(res)
+        }))
+    }
+}
+
+impl Audited for Webhook {
+    const ENTITY: &'static str = "webhook";
+    const REDACTED: &'static [&'static str] = &["secret"];
+
+    fn entity_id(&self) -> i64 {
+        self.id
+    }
+
+    fn audit_report_id(&self) -> Option<i64> {
+        None
+    }
+
+    fn audit_value(&self) -> Value {
+        serde_json::json!({
+            "id": self.id,
+            "url": self.url,
+            "secret": self.secret,
+            "events": self.events,
+            "active": self.active,
+        })
+    }
+}
+
+impl Webhook {
+    #[instrument(name = "Webhook::get_all", skip_all, err, fields(db.rows))]
+    pub fn get_all(conn: &mut PgConnection) -> Result<Vec<Self>> {
+        use crate::schema::webhooks::dsl;
+
+        let res = dsl::webhooks.order(dsl::id.asc()).load(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "Webhook::get_by_id", skip_all, err, fields(db.rows))]
+    pub fn get_by_id(id: i64, conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::webhooks::dsl;
+
+        let res = dsl::webhooks.filter(dsl::id.eq(id)).first(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "Webhook::update", skip_all, err, fields(db.rows))]
+    pub fn update(
+        id: i64,
+        changes: &NewWebhook,
+        actor_id: Option<i64>,
+        conn: &mut PgConnection,
+    ) -> Result<Self> {
+        use crate::schema::webhooks::dsl;
+
+        recorded(conn.transaction(|conn| {
+            let before = Self::get_by_id(id, conn)?;
+            let res: Self = diesel::update(dsl::webhooks.filter(dsl::id.eq(id)))
+                .set(changes)
+                .get_result(conn)?;
+            AuditLog::record(
+                AuditAction::Update,
+                Some(&before),
+                Some(&res),
+                actor_id,
+                conn,
+            )?;
+
+            Ok(res)
+        }))
+    }
+
+    #[instrument(name = "Webhook::delete", skip_all, err, fields(db.rows))]
+    pub fn delete(id: i64, actor_id: Option<i64>, conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::webhooks::dsl;
+
+        recorded(conn.transaction(|conn| {
+            let res: Self =
+                diesel::delete(dsl::webhooks.filter(dsl::id.eq(id))).get_result(conn)?;
+            AuditLog::record(AuditAction::Delete, Some(&res), None, actor_id, conn)?;
+
+            Ok(res)
+        }))
+    }
+
+    /// Value for the signature header of a delivery, `sha256=` followed by the hex encoded
+    /// HMAC-SHA256 of the request body keyed with the webhook secret
+    pub fn sign(&self, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(body);
+
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+}
+
+impl WebhookDelivery {
+    /// Queue `payload` for every active webhook subscribed to `event`
+    ///
+    /// Webhooks without event filters receive everything, and `entity.*` matches every
+    /// action on an entity. Should be called inside the transaction of the mutation, so
+    /// nothing is delivered for changes that are rolled back.
+    #[instrument(name = "WebhookDelivery::enqueue", skip_all, err, fields(db.rows))]
+    pub(crate) fn enqueue(event: &str, payload: &Value, conn: &mut PgConnection) -> Result<usize> {
+        use diesel::sql_types::{Jsonb, Text};
+
+        let wildcard = match event.split_once('.') {
+            Some((entity, _)) => format!("{entity}.*"),
+            None => event.to_owned(),
+        };
+        let res = diesel::sql_query(
+            "INSERT INTO webhook_deliveries (webhook_id, event, payload)
+            SELECT id, $1, $2 FROM webhooks
+            WHERE active
+                AND (cardinality(events) = 0 OR $1 = ANY(events) OR $3 = ANY(events))",
+        )
+        .bind::<Text, _>(event)
+        .bind::<Jsonb, _>(payload)
+        .bind::<Text, _>(wildcard)
+        .execute(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "WebhookDelivery::get_by_webhook", skip_all, err, fields(db.rows))]
+    pub fn get_by_webhook(webhook_id: i64, conn: &mut PgConnection) -> Result<Vec<Self>> {
+        use crate::schema::webhook_deliveries::dsl;
+
+        let res = dsl::webhook_deliveries
+            .filter(dsl::webhook_id.eq(webhook_id))
+            .order(dsl::id.desc())
+            .select(Self::as_select())
+            .load(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    /// Queue a delivery to be sent again, whether or not it already succeeded
+    #[instrument(name = "WebhookDelivery::redeliver", skip_all, err, fields(db.rows))]
+    pub fn redeliver(path_ids: (i64, i64), conn: &mut PgConnection) -> Result<Self> {
+        use crate::schema::webhook_deliveries::dsl;
+
+        let res = diesel::update(
+            dsl::webhook_deliveries
+                .filter(dsl::webhook_id.eq(path_ids.0))
+                .filter(dsl::id.eq(path_ids.1)),
+        )
+        .set((
+            dsl::attempts.eq(0),
+            dsl::locked_until.eq(None::<chrono::DateTime<chrono::Utc>>),
+            dsl::delivered_at.eq(None::<chrono::DateTime<chrono::Utc>>),
+        ))
+        .get_result(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    /// Lock up to `limit` undelivered deliveries of active webhooks for `lease_seconds`
+    ///
+    /// Locked rows are skipped rather than waited on, so several workers can share the queue.
+    #[instrument(name = "WebhookDelivery::claim_pending", skip_all, err, fields(db.rows))]
+    pub fn claim_pending(
+        limit: i64,
+        lease_seconds: f64,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<Self>> {
+        use diesel::sql_types::{BigInt, Double, Integer};
+
+        let res = diesel::sql_query(
+            "UPDATE webhook_deliveries SET locked_until = now() + make_interval(secs => $1)
+            WHERE id IN (
+                SELECT d.id FROM webhook_deliveries d
+                JOIN webhooks w ON w.id = d.webhook_id
+                WHERE d.delivered_at IS NULL
+                    AND d.attempts < $2
+                    AND w.active
+                    AND (d.locked_until IS NULL OR d.locked_until < now())
+                ORDER BY d.id
+                LIMIT $3
+                FOR UPDATE OF d SKIP LOCKED
+            )
+            RETURNING *",
+        )
+        .bind::<Double, _>(lease_seconds)
+        .bind::<Integer, _>(MAX_WEBHOOK_ATTEMPTS)
+        .bind::<BigInt, _>(limit)
+        .load(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    /// Deliveries still waiting to be sent, including ones being retried
+    #[instrument(name = "WebhookDelivery::count_pending", skip_all, err, fields(db.rows))]
+    pub fn count_pending(conn: &mut PgConnection) -> Result<i64> {
+        use crate::schema::webhook_deliveries::dsl;
+
+        let res = dsl::webhook_deliveries
+            .filter(dsl::delivered_at.is_null())
+            .filter(dsl::attempts.lt(MAX_WEBHOOK_ATTEMPTS))
+            .count()
+            .get_result(conn)?;
+
+        recorded(Ok(res))
+    }
+
+    #[instrument(name = "WebhookDelivery::mark_delivered", skip_all, err)]
+    pub fn mark_delivered(&self, response_status: i32, conn: &mut PgConnection) -> Result<()> {
+        use crate::schema::webhook_deliveries::dsl;
+
+        diesel::update(dsl::webhook_deliveries.filter(dsl::id.eq(self.id)))
+            .set((
+                dsl::attempts.eq(self.attempts + 1),
+                dsl::response_status.eq(Some(response_status)),
+                dsl::last_error.eq(None::<String>),
+                dsl::delivered_at.eq(Some(chrono::Utc::now())),
+                dsl::locked_until.eq(None::<chrono::DateTime<chrono::Utc>>),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Record a failed delivery, delaying the next attempt exponentially
+    #[instrument(name = "WebhookDelivery::mark_failed", skip_all, err)]
+    pub fn mark_failed(
+        &self,
+        response_status: Option<i32>,
+        error: &str,
+        conn: &mut PgConnection,
+    ) -> Result<()> {
+        use crate::schema::webhook_deliveries::dsl;
+
+        let attempts = self.attempts + 1;
+        let retry_at = chrono::Utc::now() + chrono::Duration::minutes(1 << attempts.min(10));
+
+        diesel::update(dsl::webhook_deliveries.filter(dsl::id.eq(self.id)))
+            .set((
+                dsl::attempts.eq(attempts),
+                dsl::response_status.eq(response_status),
+                dsl::last_error.eq(Some(error)),
+                dsl::locked_until.eq(Some(retry_at)),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
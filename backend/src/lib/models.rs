@@ -1,6 +1,7 @@
 use crate::schema::*;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(Queryable, Selectable, Identifiable, Debug, PartialEq)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -11,9 +12,10 @@ pub struct User {
     pub email: String,
     pub profile_picture: Option<Vec<u8>>,
     pub password_hash: String,
+    pub is_admin: bool,
 }
 
-#[derive(Deserialize, Insertable, Debug, PartialEq)]
+#[derive(Deserialize, Insertable, Debug, PartialEq, ToSchema)]
 #[diesel(table_name = users)]
 pub struct NewUser {
     pub username: String,
@@ -22,7 +24,7 @@ pub struct NewUser {
     pub password_hash: String,
 }
 
-#[derive(Serialize, Queryable, Selectable, Identifiable, Debug, PartialEq)]
+#[derive(Serialize, Queryable, Selectable, Identifiable, Debug, PartialEq, ToSchema)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 #[diesel(table_name = reports)]
 pub struct Report {
@@ -30,9 +32,10 @@ pub struct Report {
     pub owner_id: i64,
     pub title: String,
     pub description: Option<String>,
+    pub version: i32,
 }
 
-#[derive(Deserialize, Insertable, Debug, PartialEq)]
+#[derive(Deserialize, Insertable, Debug, PartialEq, ToSchema)]
 #[diesel(table_name = reports)]
 pub struct NewReport {
     pub owner_id: i64,
@@ -40,7 +43,9 @@ pub struct NewReport {
     pub description: Option<String>,
 }
 
-#[derive(Serialize, Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[derive(
+    Serialize, Queryable, Selectable, Identifiable, Associations, Debug, PartialEq, ToSchema,
+)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 #[diesel(belongs_to(Report))]
 #[diesel(table_name = report_proof)]
@@ -48,9 +53,10 @@ pub struct ReportProof {
     pub id: i64,
     pub report_id: i64,
     pub data: Vec<u8>,
+    pub version: i32,
 }
 
-#[derive(Deserialize, Insertable, Associations, Debug, PartialEq)]
+#[derive(Deserialize, Insertable, Associations, Debug, PartialEq, ToSchema)]
 #[diesel(belongs_to(Report))]
 #[diesel(table_name = report_proof)]
 pub struct NewReportProof {
@@ -58,7 +64,9 @@ pub struct NewReportProof {
     pub data: Vec<u8>,
 }
 
-#[derive(Serialize, Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[derive(
+    Serialize, Queryable, Selectable, Identifiable, Associations, Debug, PartialEq, ToSchema,
+)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 #[diesel(belongs_to(User, foreign_key = borrower_id))]
 #[diesel(belongs_to(Report))]
@@ -69,9 +77,10 @@ pub struct ReportAccess {
     pub report_id: i64,
     pub read_access: bool,
     pub write_access: bool,
+    pub version: i32,
 }
 
-#[derive(Deserialize, Insertable, Associations, Debug, PartialEq)]
+#[derive(Deserialize, Insertable, Associations, Debug, PartialEq, ToSchema)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 #[diesel(belongs_to(Report))]
 #[diesel(table_name = report_access)]
@@ -92,6 +101,7 @@ pub struct ReportLineItem {
     pub report_id: i64,
     pub item_name: String,
     pub item_price_usd: diesel::data_types::Cents,
+    pub version: i32,
 }
 
 #[derive(Insertable, Associations, Debug, PartialEq)]
@@ -103,3 +113,285 @@ pub struct NewReportLineItem {
     pub item_name: String,
     pub item_price_usd: diesel::data_types::Cents,
 }
+
+#[derive(Serialize, Queryable, Selectable, Identifiable, Debug, PartialEq, ToSchema)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = audit_log)]
+pub struct AuditLog {
+    pub id: i64,
+    pub actor_id: Option<i64>,
+    pub report_id: Option<i64>,
+    pub entity: String,
+    pub entity_id: i64,
+    pub action: String,
+    /// Changed fields, each as an object with `before` and `after` values
+    #[schema(value_type = Object)]
+    pub diff: serde_json::Value,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable, Debug, PartialEq)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = audit_log)]
+pub(crate) struct NewAuditLog {
+    pub actor_id: Option<i64>,
+    pub report_id: Option<i64>,
+    pub entity: String,
+    pub entity_id: i64,
+    pub action: String,
+    pub diff: serde_json::Value,
+}
+
+#[derive(
+    Serialize, Queryable, Selectable, Identifiable, Associations, Debug, PartialEq, ToSchema,
+)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(Report))]
+#[diesel(table_name = report_versions)]
+pub struct ReportVersion {
+    pub id: i64,
+    pub report_id: i64,
+    pub version: i32,
+    pub actor_id: Option<i64>,
+    pub reason: String,
+    /// Report fields with `items` and `proof` metadata at the time of the snapshot
+    #[schema(value_type = Object)]
+    pub snapshot: serde_json::Value,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable, Associations, Debug, PartialEq)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(Report))]
+#[diesel(table_name = report_versions)]
+pub(crate) struct NewReportVersion {
+    pub report_id: i64,
+    pub version: i32,
+    pub actor_id: Option<i64>,
+    pub reason: String,
+    pub snapshot: serde_json::Value,
+}
+
+#[derive(
+    Serialize, Queryable, Selectable, Identifiable, Associations, Debug, PartialEq, ToSchema,
+)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(Report))]
+#[diesel(belongs_to(User, foreign_key = author_id))]
+#[diesel(table_name = report_comments)]
+pub struct ReportComment {
+    pub id: i64,
+    pub report_id: i64,
+    pub line_item_id: Option<i64>,
+    pub author_id: i64,
+    pub body: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Insertable, Associations, Debug, PartialEq)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(Report))]
+#[diesel(table_name = report_comments)]
+pub struct NewReportComment {
+    pub report_id: i64,
+    pub line_item_id: Option<i64>,
+    pub author_id: i64,
+    pub body: String,
+}
+
+#[derive(
+    Serialize,
+    Deserialize,
+    Queryable,
+    Selectable,
+    Identifiable,
+    Insertable,
+    AsChangeset,
+    Debug,
+    PartialEq,
+    ToSchema,
+)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(user_id))]
+#[diesel(table_name = notification_preferences)]
+pub struct NotificationPreferences {
+    pub user_id: i64,
+    pub email_enabled: bool,
+    pub report_shared: bool,
+    pub report_submitted: bool,
+    pub report_approved: bool,
+    pub report_rejected: bool,
+    pub comment_added: bool,
+}
+
+#[derive(Serialize, Queryable, QueryableByName, Selectable, Identifiable, Debug, PartialEq)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = notification_outbox)]
+pub struct OutboxMessage {
+    pub id: i64,
+    pub user_id: i64,
+    pub event: String,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub sent_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Insertable, Debug, PartialEq)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = notification_outbox)]
+pub(crate) struct NewOutboxMessage {
+    pub user_id: i64,
+    pub event: String,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(
+    Serialize, Queryable, Selectable, Identifiable, Associations, Debug, PartialEq, ToSchema,
+)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(User))]
+#[diesel(table_name = notifications)]
+pub struct Notification {
+    pub id: i64,
+    pub user_id: i64,
+    pub event: String,
+    pub report_id: Option<i64>,
+    pub actor_id: Option<i64>,
+    pub message: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub read_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Insertable, Debug, PartialEq)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = notifications)]
+pub(crate) struct NewNotification {
+    pub user_id: i64,
+    pub event: String,
+    pub report_id: Option<i64>,
+    pub actor_id: Option<i64>,
+    pub message: String,
+}
+
+#[derive(Serialize, Queryable, Selectable, Identifiable, Debug, PartialEq, ToSchema)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = webhooks)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: Vec<Option<String>>,
+    pub active: bool,
+    pub created_by: Option<i64>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize, Insertable, AsChangeset, Debug, PartialEq, ToSchema)]
+#[diesel(table_name = webhooks)]
+pub struct NewWebhook {
+    pub url: String,
+    pub secret: String,
+    /// Events to deliver, such as `report.update` or `report_line_item.*`, or every event
+    /// when empty
+    #[serde(default)]
+    pub events: Vec<Option<String>>,
+    #[serde(default = "NewWebhook::default_active")]
+    pub active: bool,
+}
+
+#[derive(
+    Serialize,
+    Queryable,
+    QueryableByName,
+    Selectable,
+    Identifiable,
+    Associations,
+    Debug,
+    PartialEq,
+    ToSchema,
+)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(Webhook))]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub delivered_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Fields of a report which clients may change through a merge patch
+#[derive(Serialize, Deserialize, Debug, PartialEq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReportPatch {
+    pub title: String,
+    pub description: Option<String>,
+}
+
+/// Fields of a line item which clients may change through a merge patch
+#[derive(Serialize, Deserialize, Debug, PartialEq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReportLineItemPatch {
+    pub item_name: String,
+    pub item_price_usd: f64,
+}
+
+/// Fields of an access grant which clients may change through a merge patch
+#[derive(Serialize, Deserialize, Debug, PartialEq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReportAccessPatch {
+    pub read_access: bool,
+    pub write_access: bool,
+}
+
+/// Fields of a user which clients may change through a merge patch
+///
+/// Passwords and profile pictures have their own endpoints.
+#[derive(Serialize, Deserialize, Debug, PartialEq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UserPatch {
+    pub username: String,
+    pub email: String,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Clone, PartialEq)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = idempotency_keys)]
+pub struct IdempotencyKey {
+    pub key: String,
+    pub actor_id: Option<i64>,
+    pub request_sha256: String,
+    pub response_status: Option<i32>,
+    pub response_headers: Option<serde_json::Value>,
+    pub response_body: Option<Vec<u8>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub id: i64,
+    /// Until when an unfinished request holds the key, retries after it take the key over
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Insertable, Debug, PartialEq)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = idempotency_keys)]
+pub(crate) struct NewIdempotencyKey<'a> {
+    pub key: &'a str,
+    pub actor_id: Option<i64>,
+    pub request_sha256: &'a str,
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+}
@@ -1,5 +1,310 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    /// Representation of the `audit_log` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    audit_log (id) {
+        /// The `id` column of the `audit_log` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int8,
+        /// The `actor_id` column of the `audit_log` table.
+        ///
+        /// Its SQL type is `Nullable<Int8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        actor_id -> Nullable<Int8>,
+        /// The `report_id` column of the `audit_log` table.
+        ///
+        /// Its SQL type is `Nullable<Int8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        report_id -> Nullable<Int8>,
+        /// The `entity` column of the `audit_log` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 64]
+        entity -> Varchar,
+        /// The `entity_id` column of the `audit_log` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        entity_id -> Int8,
+        /// The `action` column of the `audit_log` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 16]
+        action -> Varchar,
+        /// The `diff` column of the `audit_log` table.
+        ///
+        /// Its SQL type is `Jsonb`.
+        ///
+        /// (Automatically generated by Diesel.)
+        diff -> Jsonb,
+        /// The `created_at` column of the `audit_log` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `idempotency_keys` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    idempotency_keys (id) {
+        /// The `key` column of the `idempotency_keys` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 255]
+        key -> Varchar,
+        /// The `actor_id` column of the `idempotency_keys` table.
+        ///
+        /// Its SQL type is `Nullable<Int8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        actor_id -> Nullable<Int8>,
+        /// The `request_sha256` column of the `idempotency_keys` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 64]
+        request_sha256 -> Varchar,
+        /// The `response_status` column of the `idempotency_keys` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        response_status -> Nullable<Int4>,
+        /// The `response_headers` column of the `idempotency_keys` table.
+        ///
+        /// Its SQL type is `Nullable<Jsonb>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        response_headers -> Nullable<Jsonb>,
+        /// The `response_body` column of the `idempotency_keys` table.
+        ///
+        /// Its SQL type is `Nullable<Bytea>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        response_body -> Nullable<Bytea>,
+        /// The `created_at` column of the `idempotency_keys` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamptz,
+        /// The `id` column of the `idempotency_keys` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int8,
+        /// The `locked_until` column of the `idempotency_keys` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        locked_until -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `notification_outbox` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    notification_outbox (id) {
+        /// The `id` column of the `notification_outbox` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int8,
+        /// The `user_id` column of the `notification_outbox` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_id -> Int8,
+        /// The `event` column of the `notification_outbox` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 32]
+        event -> Varchar,
+        /// The `recipient` column of the `notification_outbox` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 128]
+        recipient -> Varchar,
+        /// The `subject` column of the `notification_outbox` table.
+        ///
+        /// Its SQL type is `Text`.
+        ///
+        /// (Automatically generated by Diesel.)
+        subject -> Text,
+        /// The `body` column of the `notification_outbox` table.
+        ///
+        /// Its SQL type is `Text`.
+        ///
+        /// (Automatically generated by Diesel.)
+        body -> Text,
+        /// The `attempts` column of the `notification_outbox` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        attempts -> Int4,
+        /// The `last_error` column of the `notification_outbox` table.
+        ///
+        /// Its SQL type is `Nullable<Text>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        last_error -> Nullable<Text>,
+        /// The `locked_until` column of the `notification_outbox` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        locked_until -> Nullable<Timestamptz>,
+        /// The `created_at` column of the `notification_outbox` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamptz,
+        /// The `sent_at` column of the `notification_outbox` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        sent_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `notification_preferences` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    notification_preferences (user_id) {
+        /// The `user_id` column of the `notification_preferences` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_id -> Int8,
+        /// The `email_enabled` column of the `notification_preferences` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        email_enabled -> Bool,
+        /// The `report_shared` column of the `notification_preferences` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        report_shared -> Bool,
+        /// The `report_submitted` column of the `notification_preferences` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        report_submitted -> Bool,
+        /// The `report_approved` column of the `notification_preferences` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        report_approved -> Bool,
+        /// The `report_rejected` column of the `notification_preferences` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        report_rejected -> Bool,
+        /// The `comment_added` column of the `notification_preferences` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        comment_added -> Bool,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `notifications` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    notifications (id) {
+        /// The `id` column of the `notifications` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int8,
+        /// The `user_id` column of the `notifications` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_id -> Int8,
+        /// The `event` column of the `notifications` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 32]
+        event -> Varchar,
+        /// The `report_id` column of the `notifications` table.
+        ///
+        /// Its SQL type is `Nullable<Int8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        report_id -> Nullable<Int8>,
+        /// The `actor_id` column of the `notifications` table.
+        ///
+        /// Its SQL type is `Nullable<Int8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        actor_id -> Nullable<Int8>,
+        /// The `message` column of the `notifications` table.
+        ///
+        /// Its SQL type is `Text`.
+        ///
+        /// (Automatically generated by Diesel.)
+        message -> Text,
+        /// The `created_at` column of the `notifications` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamptz,
+        /// The `read_at` column of the `notifications` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        read_at -> Nullable<Timestamptz>,
+    }
+}
+
 diesel::table! {
     /// Representation of the `report_access` table.
     ///
@@ -35,6 +340,62 @@ diesel::table! {
         ///
         /// (Automatically generated by Diesel.)
         write_access -> Bool,
+        /// The `version` column of the `report_access` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        version -> Int4,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `report_comments` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    report_comments (id) {
+        /// The `id` column of the `report_comments` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int8,
+        /// The `report_id` column of the `report_comments` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        report_id -> Int8,
+        /// The `line_item_id` column of the `report_comments` table.
+        ///
+        /// Its SQL type is `Nullable<Int8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        line_item_id -> Nullable<Int8>,
+        /// The `author_id` column of the `report_comments` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        author_id -> Int8,
+        /// The `body` column of the `report_comments` table.
+        ///
+        /// Its SQL type is `Text`.
+        ///
+        /// (Automatically generated by Diesel.)
+        body -> Text,
+        /// The `created_at` column of the `report_comments` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamptz,
+        /// The `updated_at` column of the `report_comments` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        updated_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -68,6 +429,12 @@ diesel::table! {
         ///
         /// (Automatically generated by Diesel.)
         item_price_usd -> Money,
+        /// The `version` column of the `report_line_items` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        version -> Int4,
     }
 }
 
@@ -94,6 +461,63 @@ diesel::table! {
         ///
         /// (Automatically generated by Diesel.)
         data -> Bytea,
+        /// The `version` column of the `report_proof` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        version -> Int4,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `report_versions` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    report_versions (id) {
+        /// The `id` column of the `report_versions` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int8,
+        /// The `report_id` column of the `report_versions` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        report_id -> Int8,
+        /// The `version` column of the `report_versions` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        version -> Int4,
+        /// The `actor_id` column of the `report_versions` table.
+        ///
+        /// Its SQL type is `Nullable<Int8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        actor_id -> Nullable<Int8>,
+        /// The `reason` column of the `report_versions` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 64]
+        reason -> Varchar,
+        /// The `snapshot` column of the `report_versions` table.
+        ///
+        /// Its SQL type is `Jsonb`.
+        ///
+        /// (Automatically generated by Diesel.)
+        snapshot -> Jsonb,
+        /// The `created_at` column of the `report_versions` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamptz,
     }
 }
 
@@ -127,6 +551,12 @@ diesel::table! {
         ///
         /// (Automatically generated by Diesel.)
         description -> Nullable<Text>,
+        /// The `version` column of the `reports` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        version -> Int4,
     }
 }
 
@@ -168,16 +598,161 @@ diesel::table! {
         /// (Automatically generated by Diesel.)
         #[max_length = 255]
         password_hash -> Varchar,
+        /// The `is_admin` column of the `users` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        is_admin -> Bool,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `webhook_deliveries` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    webhook_deliveries (id) {
+        /// The `id` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int8,
+        /// The `webhook_id` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        webhook_id -> Int8,
+        /// The `event` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 64]
+        event -> Varchar,
+        /// The `payload` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Jsonb`.
+        ///
+        /// (Automatically generated by Diesel.)
+        payload -> Jsonb,
+        /// The `attempts` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        attempts -> Int4,
+        /// The `response_status` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        response_status -> Nullable<Int4>,
+        /// The `last_error` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Nullable<Text>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        last_error -> Nullable<Text>,
+        /// The `locked_until` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        locked_until -> Nullable<Timestamptz>,
+        /// The `created_at` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamptz,
+        /// The `delivered_at` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        delivered_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    /// Representation of the `webhooks` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    webhooks (id) {
+        /// The `id` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Int8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int8,
+        /// The `url` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Text`.
+        ///
+        /// (Automatically generated by Diesel.)
+        url -> Text,
+        /// The `secret` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        #[max_length = 255]
+        secret -> Varchar,
+        /// The `events` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Array<Nullable<Text>>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        events -> Array<Nullable<Text>>,
+        /// The `active` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        active -> Bool,
+        /// The `created_by` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Nullable<Int8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_by -> Nullable<Int8>,
+        /// The `created_at` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_at -> Timestamptz,
     }
 }
 
+diesel::joinable!(notification_outbox -> users (user_id));
+diesel::joinable!(notification_preferences -> users (user_id));
+diesel::joinable!(notifications -> reports (report_id));
+diesel::joinable!(notifications -> users (user_id));
 diesel::joinable!(report_access -> users (borrower_id));
+diesel::joinable!(report_comments -> report_line_items (line_item_id));
+diesel::joinable!(report_comments -> reports (report_id));
+diesel::joinable!(report_comments -> users (author_id));
+diesel::joinable!(report_versions -> reports (report_id));
 diesel::joinable!(reports -> users (owner_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
+diesel::joinable!(webhooks -> users (created_by));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    idempotency_keys,
+    notification_outbox,
+    notification_preferences,
+    notifications,
     report_access,
+    report_comments,
     report_line_items,
     report_proof,
+    report_versions,
     reports,
     users,
+    webhook_deliveries,
+    webhooks,
 );
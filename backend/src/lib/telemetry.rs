@@ -0,0 +1,109 @@
+//! Span export for the server and the models
+//!
+//! Every model call runs in a span named after it, such as `Report::get_by_owner`,
+//! recording the rows it returned or changed as `db.rows`.
+
+use anyhow::{Context, Result};
+use opentelemetry::{global, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    export::trace::SpanExporter,
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{self, Sampler, TracerProvider},
+    Resource,
+};
+use serde::Deserialize;
+use std::time::Duration;
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, Registry};
+
+/// Targets whose spans are exported: the server's router and the models
+const TRACED: [&str; 2] = ["server", "expenser"];
+
+/// OpenTelemetry trace export, read from the `[tracing]` section of the server config
+///
+/// Off unless a collector endpoint is set.
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingConfig {
+    /// Base url of an OTLP/HTTP collector, e.g. `http://localhost:4318`
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+    /// Fraction of new traces to export, between 0 and 1; traces started by a caller
+    /// follow the caller's sampling decision
+    pub sample_ratio: f64,
+    pub export_timeout_secs: u64,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "expenser".to_owned(),
+            sample_ratio: 1.0,
+            export_timeout_secs: 10,
+        }
+    }
+}
+
+/// Install the tracing subscriber, exporting spans over OTLP when an endpoint is configured
+///
+/// A subscriber is installed either way, otherwise spans would be written to the log.
+/// Keep the returned provider alive for as long as spans should be exported.
+pub fn init(config: &TracingConfig) -> Result<Option<TracerProvider>> {
+    let Some(endpoint) = &config.otlp_endpoint else {
+        tracing::subscriber::set_global_default(tracing::subscriber::NoSubscriber::default())
+            .context("Unable to install tracing subscriber")?;
+        return Ok(None);
+    };
+
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(endpoint)
+        .with_timeout(Duration::from_secs(config.export_timeout_secs))
+        .build_span_exporter()
+        .context("Unable to create OTLP exporter")?;
+    let provider = provider(exporter, config);
+    install(&provider)?;
+    log::info!("Exporting traces to {endpoint}");
+
+    Ok(Some(provider))
+}
+
+/// Batch spans into `exporter`, which can be swapped for an in-memory one in tests
+pub fn provider(exporter: impl SpanExporter + 'static, config: &TracingConfig) -> TracerProvider {
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+    let resource = Resource::new([KeyValue::new("service.name", config.service_name.clone())]);
+
+    TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_config(
+            trace::config()
+                .with_sampler(sampler)
+                .with_resource(resource),
+        )
+        .build()
+}
+
+/// Send spans to `provider` and accept W3C trace context from callers
+pub fn install(provider: &TracerProvider) -> Result<()> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let targets = TRACED.into_iter().fold(Targets::new(), |targets, target| {
+        targets.with_target(target, tracing::Level::TRACE)
+    });
+    let subscriber = Registry::default()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("expenser")))
+        .with(targets);
+    tracing::subscriber::set_global_default(subscriber)
+        .context("Unable to install tracing subscriber")
+}
+
+/// Export anything still buffered, blocking until the exporter has finished
+pub fn flush(provider: &TracerProvider) {
+    for res in provider.force_flush() {
+        if let Err(e) = res {
+            log::error!("Unable to export spans: {e}");
+        }
+    }
+}
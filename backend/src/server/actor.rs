@@ -0,0 +1,41 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+};
+
+/// Header carrying the id of the user making a request
+pub const ACTOR_HEADER: &str = "x-user-id";
+
+/// User making a request, used to attribute changes in the audit log
+///
+/// Read from the `X-User-Id` header until requests are authenticated.
+/// Requests without the header are recorded without an actor.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Actor(pub Option<i64>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(ACTOR_HEADER) else {
+            return Ok(Self(None));
+        };
+
+        match value.to_str().ok().and_then(|id| id.parse().ok()) {
+            Some(id) => Ok(Self(Some(id))),
+            None => {
+                log::warn!("Rejected malformed {ACTOR_HEADER} header");
+                Err(StatusCode::BAD_REQUEST)
+            }
+        }
+    }
+}
+
+impl Actor {
+    /// Id of the acting user, rejecting anonymous requests
+    pub fn required(self) -> Result<i64, StatusCode> {
+        self.0.ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
@@ -0,0 +1,220 @@
+use crate::config::{ApiConfig, DeprecationConfig};
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::{FromRequest, FromRequestParts, OriginalUri, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    BoxError, Json, Router,
+};
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+
+/// Version of the API a request was routed through
+///
+/// Handlers are shared between versions. Bodies whose format differs between versions
+/// go through [`VersionedJson`], everything else is the same in every version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+    pub const ALL: [Self; 1] = [Self::V1];
+    pub const LATEST: Self = Self::V1;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::V1 => "v1",
+        }
+    }
+
+    pub fn prefix(self) -> String {
+        format!("/api/{}", self.name())
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ApiVersion {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get().copied().ok_or_else(|| {
+            log::error!(
+                "{} is not routed through a versioned tree",
+                parts.uri.path()
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+    }
+}
+
+/// Body which is serialized differently depending on the API version
+pub trait IntoVersioned {
+    type V1: Serialize;
+
+    fn into_v1(self) -> Self::V1;
+}
+
+/// Body which is deserialized differently depending on the API version
+pub trait FromVersioned: Sized {
+    type V1: DeserializeOwned;
+
+    fn from_v1(body: Self::V1) -> Self;
+}
+
+impl<T: IntoVersioned> IntoVersioned for Vec<T> {
+    type V1 = Vec<T::V1>;
+
+    fn into_v1(self) -> Self::V1 {
+        self.into_iter().map(T::into_v1).collect()
+    }
+}
+
+impl<T: FromVersioned> FromVersioned for Vec<T> {
+    type V1 = Vec<T::V1>;
+
+    fn from_v1(body: Self::V1) -> Self {
+        body.into_iter().map(T::from_v1).collect()
+    }
+}
+
+/// JSON body in the format of the API version the request was made to
+pub struct VersionedJson<T>(pub ApiVersion, pub T);
+
+impl<T: IntoVersioned> IntoResponse for VersionedJson<T> {
+    fn into_response(self) -> Response {
+        match self.0 {
+            ApiVersion::V1 => Json(self.1.into_v1()).into_response(),
+        }
+    }
+}
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for VersionedJson<T>
+where
+    T: FromVersioned,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let (mut parts, body) = request.into_parts();
+        let version = ApiVersion::from_request_parts(&mut parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let request = Request::from_parts(parts, body);
+
+        let body = match version {
+            ApiVersion::V1 => {
+                let Json(body) = Json::from_request(request, state)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                T::from_v1(body)
+            }
+        };
+
+        Ok(Self(version, body))
+    }
+}
+
+/// Routes of one version, served under their own prefix
+#[derive(Clone)]
+pub struct Tree {
+    pub prefix: String,
+    version: ApiVersion,
+    deprecation: Option<DeprecationConfig>,
+}
+
+/// Every version's tree, and the unversioned one if it is still served
+pub fn trees(config: &ApiConfig) -> Vec<Tree> {
+    let mut trees: Vec<Tree> = ApiVersion::ALL
+        .into_iter()
+        .map(|version| Tree {
+            prefix: version.prefix(),
+            version,
+            deprecation: config.deprecations.get(version.name()).copied(),
+        })
+        .collect();
+    if config.unversioned {
+        trees.push(Tree {
+            prefix: "/api".to_owned(),
+            version: ApiVersion::V1,
+            deprecation: config.deprecations.get("unversioned").copied(),
+        });
+    }
+
+    trees
+}
+
+impl Tree {
+    /// Tag requests to `router` with the tree's version, and announce its deprecation
+    pub fn layer(self, router: Router) -> Router {
+        router.layer(axum::middleware::from_fn_with_state(Arc::new(self), tag))
+    }
+
+    /// `Deprecation`, `Sunset` and a `Link` to the same route in the latest version
+    fn deprecation_headers(&self, deprecation: &DeprecationConfig, path: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let deprecated_at = format!("@{}", deprecation.deprecated_at.timestamp());
+        headers.insert(
+            "deprecation",
+            HeaderValue::from_str(&deprecated_at).expect("Deprecation is a valid header"),
+        );
+        if let Some(sunset_at) = deprecation.sunset_at {
+            let sunset_at = sunset_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+            headers.insert(
+                "sunset",
+                HeaderValue::from_str(&sunset_at).expect("Sunset is a valid header"),
+            );
+        }
+
+        let latest = ApiVersion::LATEST.prefix();
+        if self.prefix != latest {
+            let route = path.strip_prefix(self.prefix.as_str()).unwrap_or(path);
+            if let Ok(link) =
+                HeaderValue::from_str(&format!("<{latest}{route}>; rel=\"successor-version\""))
+            {
+                headers.insert(header::LINK, link);
+            }
+        }
+
+        headers
+    }
+}
+
+/// Insert the tree's [`ApiVersion`] for handlers, and mark deprecated versions
+///
+/// Once a version's sunset has passed its routes answer 410 Gone.
+async fn tag<B>(State(tree): State<Arc<Tree>>, mut request: Request<B>, next: Next<B>) -> Response {
+    request.extensions_mut().insert(tree.version);
+    let Some(deprecation) = tree.deprecation else {
+        return next.run(request).await;
+    };
+
+    let path = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path().to_owned(),
+        None => request.uri().path().to_owned(),
+    };
+    let headers = tree.deprecation_headers(&deprecation, &path);
+    if deprecation
+        .sunset_at
+        .is_some_and(|sunset_at| sunset_at <= Utc::now())
+    {
+        log::info!(
+            "Refused {} {path}, {} is past its sunset",
+            request.method(),
+            tree.prefix
+        );
+        return (StatusCode::GONE, headers).into_response();
+    }
+
+    let mut response = next.run(request).await;
+    response.headers_mut().extend(headers);
+    response
+}
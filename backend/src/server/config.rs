@@ -0,0 +1,810 @@
+use crate::api_version::ApiVersion;
+use anyhow::{bail, Context, Result};
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use chrono::{DateTime, TimeZone, Utc};
+use clap::Parser;
+use expenser::database::PoolConfig;
+use expenser::telemetry::TracingConfig;
+use lettre::message::Mailbox;
+use serde::Deserialize;
+use std::{collections::BTreeMap, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+use toml::{Table, Value};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Used when neither `--config` nor `EXPENSER_CONFIG` is given, if it exists
+const DEFAULT_CONFIG_FILE: &str = "expenser.toml";
+/// Prefix of environment variables overriding config values, e.g. `EXPENSER_SERVER__BIND`
+const ENV_PREFIX: &str = "EXPENSER_";
+/// Separates the section from the key in environment variable names
+const ENV_SEPARATOR: &str = "__";
+/// Variables read before the config system existed, mapped onto their config keys
+const LEGACY_ENV: [(&str, &str); 4] = [
+    ("DATABASE_URL", "database.url"),
+    ("MIGRATE", "database.migrate"),
+    (
+        "IDEMPOTENCY_WINDOW_HOURS",
+        "server.idempotency_window_hours",
+    ),
+    ("SMTP_PORT", "smtp.port"),
+];
+/// Legacy SMTP variables which are always strings, so that e.g. a numeric password stays one
+const SMTP_ENV: [(&str, &str); 5] = [
+    ("SMTP_HOST", "smtp.host"),
+    ("SMTP_TLS", "smtp.tls"),
+    ("SMTP_USERNAME", "smtp.username"),
+    ("SMTP_PASSWORD", "smtp.password"),
+    ("SMTP_FROM", "smtp.from"),
+];
+/// Standard OpenTelemetry variables, mapped onto their config keys
+const OTEL_ENV: [(&str, &str); 2] = [
+    ("OTEL_EXPORTER_OTLP_ENDPOINT", "tracing.otlp_endpoint"),
+    ("OTEL_SERVICE_NAME", "tracing.service_name"),
+];
+
+// Command line flags, the highest priority layer
+#[derive(Parser)]
+#[command(version, about = "Expenser API server")]
+struct Args {
+    /// TOML config file
+    #[arg(short, long, env = "EXPENSER_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address to listen on
+    #[arg(long)]
+    bind: Option<SocketAddr>,
+    /// Postgres connection url
+    #[arg(long)]
+    database_url: Option<String>,
+    /// Run pending migrations at startup
+    #[arg(long)]
+    migrate: bool,
+    /// One of off, error, warn, info, debug or trace
+    #[arg(long)]
+    log_level: Option<String>,
+    /// Directory for log files
+    #[arg(long)]
+    log_dir: Option<PathBuf>,
+    /// PEM certificate chain, enables TLS together with `--tls-key`
+    #[arg(long)]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key
+    #[arg(long)]
+    tls_key: Option<PathBuf>,
+}
+
+/// Server configuration
+///
+/// Built from defaults, then a TOML file, then `EXPENSER_<SECTION>__<KEY>` environment
+/// variables, then command line flags, with later layers overriding earlier ones.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub tls: TlsConfig,
+    pub database: PoolConfig,
+    pub log: LogConfig,
+    pub smtp: SmtpConfig,
+    pub limits: LimitsConfig,
+    pub rate_limit: RateLimitConfig,
+    pub cors: CorsConfig,
+    pub security: SecurityConfig,
+    pub tracing: TracingConfig,
+    pub metrics: MetricsConfig,
+    pub api: ApiConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: SocketAddr,
+    /// How long idempotency keys and their responses are kept
+    pub idempotency_window_hours: i64,
+    /// How long open requests, then background workers, get to finish on shutdown
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            idempotency_window_hours: 24,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+/// Certificate and key for serving HTTPS, TLS is off unless both are set
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    /// Plain HTTP address redirecting every request to HTTPS, e.g. `0.0.0.0:80`
+    pub redirect_bind: Option<SocketAddr>,
+    /// How often the certificate and key are checked for changes, 0 to only reload on SIGHUP
+    pub reload_interval_secs: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            cert_path: None,
+            key_path: None,
+            redirect_bind: None,
+            reload_interval_secs: 30,
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn paths(&self) -> Option<(&PathBuf, &PathBuf)> {
+        self.cert_path.as_ref().zip(self.key_path.as_ref())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// One of `off`, `error`, `warn`, `info`, `debug` or `trace`
+    pub level: String,
+    /// Levels for individual modules, keyed by module path such as `server::webhooks`,
+    /// overriding `level` for that module and everything below it
+    pub modules: BTreeMap<String, String>,
+    pub format: LogFormat,
+    /// Directory for log files, none to only log to stdout
+    pub directory: Option<PathBuf>,
+    pub stdout: bool,
+    /// Start a new log file every hour or day, or `never`
+    pub rotation: LogRotation,
+    /// Start a new log file once the current one reaches this size
+    pub max_file_bytes: Option<u64>,
+    /// Rotated log files to keep, older ones are deleted
+    pub max_files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_owned(),
+            modules: BTreeMap::new(),
+            format: LogFormat::Text,
+            directory: Some(PathBuf::from("logs")),
+            stdout: true,
+            rotation: LogRotation::Daily,
+            max_file_bytes: Some(100 * 1024 * 1024),
+            max_files: 14,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn level(&self) -> Result<log::LevelFilter> {
+        log::LevelFilter::from_str(&self.level)
+            .with_context(|| format!("log.level \"{}\" is not a log level", self.level))
+    }
+
+    pub fn modules(&self) -> Result<BTreeMap<String, log::LevelFilter>> {
+        self.modules
+            .iter()
+            .map(|(module, level)| {
+                let level = log::LevelFilter::from_str(level).with_context(|| {
+                    format!("log.modules.\"{module}\" \"{level}\" is not a log level")
+                })?;
+                Ok((module.clone(), level))
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Never,
+    Hourly,
+    Daily,
+}
+
+/// Server for notification emails, which stay queued while `host` is unset
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub host: Option<String>,
+    /// Defaults to the standard port of the TLS mode
+    pub port: Option<u16>,
+    pub tls: SmtpTls,
+    /// Set together with `password`, or neither
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Mailbox the emails are sent from, e.g. `Expenser <noreply@example.com>`
+    pub from: String,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            port: None,
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            from: "Expenser <noreply@localhost>".to_owned(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain connection, for local catch-all servers
+    None,
+    Starttls,
+    Tls,
+}
+
+/// Prometheus metrics, only served on their own address so they stay off the public listener
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address serving `/metrics`, e.g. `127.0.0.1:9090`, metrics are not served if unset
+    pub bind: Option<SocketAddr>,
+    /// How often report, user and queue totals are counted
+    pub refresh_interval_secs: u64,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            bind: None,
+            refresh_interval_secs: 30,
+        }
+    }
+}
+
+/// Request body limits in bytes
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_body_bytes: usize,
+    /// Applies to profile picture and proof uploads instead of `max_body_bytes`
+    pub max_upload_bytes: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 2 * 1024 * 1024,
+            max_upload_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+/// Token bucket rate limits, each applied per client address
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Take the client address from the last `X-Forwarded-For` entry, only enable
+    /// behind a proxy which appends it
+    pub trust_forwarded_for: bool,
+    /// Every API request
+    pub requests: Quota,
+    /// Profile picture and proof uploads
+    pub uploads: Quota,
+    /// Password changes
+    pub auth: Quota,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            trust_forwarded_for: false,
+            requests: Quota {
+                per_minute: 600,
+                burst: 100,
+            },
+            uploads: Quota {
+                per_minute: 10,
+                burst: 5,
+            },
+            auth: Quota {
+                per_minute: 5,
+                burst: 5,
+            },
+        }
+    }
+}
+
+/// Requests refill at `per_minute`, up to `burst` can be made at once
+#[derive(Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Quota {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+/// Cross-origin requests are refused unless at least one origin is allowed
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins such as `https://expenser.example.com`, or `*` for any
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            allowed_headers: [
+                "content-type",
+                "if-match",
+                "idempotency-key",
+                "x-request-id",
+                "x-user-id",
+            ]
+            .map(String::from)
+            .to_vec(),
+            allow_credentials: false,
+            max_age_secs: 3600,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Layer answering preflight requests and adding CORS headers, if any origin is allowed
+    pub fn layer(&self) -> Result<Option<CorsLayer>> {
+        if self.allowed_origins.is_empty() {
+            return Ok(None);
+        }
+
+        let origins = if self.allowed_origins.iter().any(|origin| origin == "*") {
+            AllowOrigin::any()
+        } else {
+            AllowOrigin::list(
+                self.allowed_origins
+                    .iter()
+                    .map(|origin| HeaderValue::from_str(origin))
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+        };
+        let methods = self
+            .allowed_methods
+            .iter()
+            .map(|method| Method::from_str(method))
+            .collect::<Result<Vec<_>, _>>()?;
+        let headers = self
+            .allowed_headers
+            .iter()
+            .map(|header| HeaderName::from_str(header))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let layer = CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .allow_credentials(self.allow_credentials)
+            .expose_headers([crate::request_id::REQUEST_ID.clone()])
+            .max_age(Duration::from_secs(self.max_age_secs));
+
+        Ok(Some(layer))
+    }
+}
+
+/// Headers asking browsers to restrict what they do with API responses
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityConfig {
+    /// `Content-Security-Policy`, empty to leave it out
+    pub content_security_policy: String,
+    /// `X-Frame-Options`, empty to leave it out
+    pub frame_options: String,
+    /// `Strict-Transport-Security` lifetime, only sent when serving HTTPS, 0 to leave it out
+    pub hsts_max_age_secs: u64,
+    pub hsts_include_subdomains: bool,
+    /// Refuse state-changing requests which carry cookies from origins other than the
+    /// API's own and `cors.allowed_origins`
+    pub csrf_protection: bool,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            content_security_policy: "default-src 'none'; frame-ancestors 'none'".to_owned(),
+            frame_options: "DENY".to_owned(),
+            hsts_max_age_secs: 365 * 24 * 60 * 60,
+            hsts_include_subdomains: false,
+            csrf_protection: true,
+        }
+    }
+}
+
+impl SecurityConfig {
+    /// Headers added to every response which does not already set them
+    pub fn headers(&self, tls: bool) -> Result<Vec<(HeaderName, HeaderValue)>> {
+        let mut headers = vec![
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ),
+            (
+                header::REFERRER_POLICY,
+                HeaderValue::from_static("no-referrer"),
+            ),
+        ];
+        if !self.content_security_policy.is_empty() {
+            headers.push((
+                header::CONTENT_SECURITY_POLICY,
+                HeaderValue::from_str(&self.content_security_policy)?,
+            ));
+        }
+        if !self.frame_options.is_empty() {
+            headers.push((
+                header::X_FRAME_OPTIONS,
+                HeaderValue::from_str(&self.frame_options)?,
+            ));
+        }
+        if tls && self.hsts_max_age_secs > 0 {
+            let mut hsts = format!("max-age={}", self.hsts_max_age_secs);
+            if self.hsts_include_subdomains {
+                hsts.push_str("; includeSubDomains");
+            }
+            headers.push((
+                header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_str(&hsts)?,
+            ));
+        }
+
+        Ok(headers)
+    }
+}
+
+/// Which API versions are served, and which of them are on their way out
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Also serve the routes from before versioning under `/api`, behaving as v1
+    pub unversioned: bool,
+    /// Keyed by `unversioned` or a version such as `v1`
+    pub deprecations: BTreeMap<String, DeprecationConfig>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            unversioned: true,
+            deprecations: BTreeMap::from([(
+                "unversioned".to_owned(),
+                DeprecationConfig {
+                    deprecated_at: Utc.with_ymd_and_hms(2026, 10, 19, 0, 0, 0).unwrap(),
+                    sunset_at: None,
+                },
+            )]),
+        }
+    }
+}
+
+/// Sent in `Deprecation` and `Sunset` headers on every response of a version
+#[derive(Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct DeprecationConfig {
+    pub deprecated_at: DateTime<Utc>,
+    /// After this the version's routes answer 410 Gone
+    pub sunset_at: Option<DateTime<Utc>>,
+}
+
+impl Config {
+    /// Load every layer and validate the result
+    pub fn load() -> Result<Self> {
+        let args = Args::parse();
+        let mut layers = Table::new();
+
+        let file = match &args.config {
+            Some(path) => Some(path.clone()),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()),
+        };
+        if let Some(path) = file {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Unable to read config file {}", path.display()))?;
+            let table = toml::from_str(&text)
+                .with_context(|| format!("Unable to parse config file {}", path.display()))?;
+            merge(&mut layers, table);
+        }
+        merge(&mut layers, env_layer()?);
+        merge(&mut layers, args.into_layer());
+
+        let config = Config::deserialize(Value::Table(layers)).context("Invalid configuration")?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Check values that parse but cannot work, reporting every problem at once
+    fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if self.server.idempotency_window_hours <= 0 {
+            problems.push("server.idempotency_window_hours must be positive".to_owned());
+        }
+
+        match (&self.tls.cert_path, &self.tls.key_path) {
+            (Some(_), None) => {
+                problems.push("tls.cert_path is set without tls.key_path".to_owned())
+            }
+            (None, Some(_)) => {
+                problems.push("tls.key_path is set without tls.cert_path".to_owned())
+            }
+            _ => {}
+        }
+        for (key, path) in [
+            ("tls.cert_path", &self.tls.cert_path),
+            ("tls.key_path", &self.tls.key_path),
+        ] {
+            if let Some(path) = path.as_ref().filter(|path| !path.is_file()) {
+                problems.push(format!("{key} {} is not a file", path.display()));
+            }
+        }
+        if let Some(redirect_bind) = self.tls.redirect_bind {
+            if self.tls.paths().is_none() {
+                problems.push("tls.redirect_bind needs tls.cert_path and tls.key_path".to_owned());
+            }
+            if redirect_bind == self.server.bind {
+                problems.push("tls.redirect_bind cannot be the same as server.bind".to_owned());
+            }
+        }
+
+        if self.database.url.is_empty() {
+            problems.push("database.url is not set, use DATABASE_URL or --database-url".to_owned());
+        } else if let Err(e) = tokio_postgres::Config::from_str(&self.database.url) {
+            // The report event listener connects with tokio-postgres rather than libpq
+            problems.push(format!(
+                "database.url is not supported for report events: {e}"
+            ));
+        }
+        if self.database.max_size == 0 {
+            problems.push("database.max_size must be at least 1".to_owned());
+        }
+        if self.database.min_idle > Some(self.database.max_size) {
+            problems.push("database.min_idle cannot be larger than database.max_size".to_owned());
+        }
+        if self.database.connection_timeout_secs == 0 {
+            problems.push("database.connection_timeout_secs must be at least 1".to_owned());
+        }
+
+        if let Err(e) = self.log.level() {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = self.log.modules() {
+            problems.push(e.to_string());
+        }
+        if self.log.max_file_bytes == Some(0) {
+            problems.push("log.max_file_bytes must be at least 1".to_owned());
+        }
+        if self.log.directory.is_none() && !self.log.stdout {
+            problems.push(
+                "log.directory is unset and log.stdout is false, logs would go nowhere".to_owned(),
+            );
+        }
+
+        if self.smtp.host.as_deref() == Some("") {
+            problems.push("smtp.host cannot be empty, leave it unset to disable email".to_owned());
+        }
+        if self.smtp.port == Some(0) {
+            problems.push("smtp.port must be at least 1".to_owned());
+        }
+        if self.smtp.username.is_some() != self.smtp.password.is_some() {
+            problems.push("smtp.username and smtp.password must be set together".to_owned());
+        }
+        if let Err(e) = self.smtp.from.parse::<Mailbox>() {
+            problems.push(format!(
+                "smtp.from \"{}\" is not a mailbox: {e}",
+                self.smtp.from
+            ));
+        }
+
+        if self.limits.max_body_bytes == 0 || self.limits.max_upload_bytes == 0 {
+            problems.push("limits must be at least 1 byte".to_owned());
+        }
+
+        for (name, quota) in [
+            ("requests", self.rate_limit.requests),
+            ("uploads", self.rate_limit.uploads),
+            ("auth", self.rate_limit.auth),
+        ] {
+            if quota.per_minute == 0 || quota.burst == 0 {
+                problems.push(format!(
+                    "rate_limit.{name} per_minute and burst must be at least 1"
+                ));
+            }
+        }
+
+        if self.cors.allow_credentials && self.cors.allowed_origins.iter().any(|o| o == "*") {
+            problems.push("cors.allow_credentials cannot be used with the `*` origin".to_owned());
+        }
+        if let Err(e) = self.cors.layer() {
+            problems.push(format!(
+                "cors contains an invalid origin, method or header: {e}"
+            ));
+        }
+
+        if let Err(e) = self.security.headers(self.tls.paths().is_some()) {
+            problems.push(format!("security contains an invalid header value: {e}"));
+        }
+
+        if let Some(endpoint) = &self.tracing.otlp_endpoint {
+            match endpoint.parse::<axum::http::Uri>() {
+                Ok(uri) if matches!(uri.scheme_str(), Some("http" | "https")) => {}
+                _ => problems.push(format!(
+                    "tracing.otlp_endpoint \"{endpoint}\" is not an http or https url"
+                )),
+            }
+        }
+        if !(0.0..=1.0).contains(&self.tracing.sample_ratio) {
+            problems.push("tracing.sample_ratio must be between 0 and 1".to_owned());
+        }
+
+        if let Some(metrics_bind) = self.metrics.bind {
+            if metrics_bind == self.server.bind {
+                problems.push("metrics.bind cannot be the same as server.bind".to_owned());
+            }
+            if Some(metrics_bind) == self.tls.redirect_bind {
+                problems.push("metrics.bind cannot be the same as tls.redirect_bind".to_owned());
+            }
+        }
+        if self.metrics.refresh_interval_secs == 0 {
+            problems.push("metrics.refresh_interval_secs must be at least 1".to_owned());
+        }
+
+        for (name, deprecation) in &self.api.deprecations {
+            if name != "unversioned" && !ApiVersion::ALL.iter().any(|v| v.name() == name) {
+                problems.push(format!(
+                    "api.deprecations.{name} is not \"unversioned\" or an API version"
+                ));
+            }
+            if deprecation
+                .sunset_at
+                .is_some_and(|sunset_at| sunset_at < deprecation.deprecated_at)
+            {
+                problems.push(format!(
+                    "api.deprecations.{name}.sunset_at is before its deprecated_at"
+                ));
+            }
+        }
+        if self
+            .api
+            .deprecations
+            .get(ApiVersion::LATEST.name())
+            .is_some_and(|deprecation| deprecation.sunset_at.is_some())
+        {
+            problems.push(format!(
+                "api.deprecations.{} cannot have a sunset_at, it is the latest version",
+                ApiVersion::LATEST.name()
+            ));
+        }
+
+        if !problems.is_empty() {
+            bail!("Invalid configuration:\n  {}", problems.join("\n  "));
+        }
+
+        Ok(())
+    }
+}
+
+impl Args {
+    fn into_layer(self) -> Table {
+        let mut layer = Table::new();
+
+        if let Some(bind) = self.bind {
+            set(&mut layer, "server.bind", Value::String(bind.to_string()));
+        }
+        if let Some(url) = self.database_url {
+            set(&mut layer, "database.url", Value::String(url));
+        }
+        if self.migrate {
+            set(&mut layer, "database.migrate", Value::Boolean(true));
+        }
+        if let Some(level) = self.log_level {
+            set(&mut layer, "log.level", Value::String(level));
+        }
+        if let Some(dir) = self.log_dir {
+            set(&mut layer, "log.directory", path_value(dir));
+        }
+        if let Some(cert) = self.tls_cert {
+            set(&mut layer, "tls.cert_path", path_value(cert));
+        }
+        if let Some(key) = self.tls_key {
+            set(&mut layer, "tls.key_path", path_value(key));
+        }
+
+        layer
+    }
+}
+
+/// Config values from `EXPENSER_<SECTION>__<KEY>` and the legacy variables
+///
+/// Values are read as TOML where they parse (numbers, booleans, arrays) and as strings otherwise.
+fn env_layer() -> Result<Table> {
+    let mut layer = Table::new();
+
+    for (var, key) in LEGACY_ENV.into_iter().chain(OTEL_ENV) {
+        if let Ok(value) = std::env::var(var) {
+            set(&mut layer, key, env_value(value));
+        }
+    }
+    for (var, key) in SMTP_ENV {
+        if let Ok(value) = std::env::var(var) {
+            set(&mut layer, key, Value::String(value));
+        }
+    }
+    for (var, value) in std::env::vars() {
+        let Some(name) = var.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        if name == "CONFIG" {
+            continue;
+        }
+        let Some((section, key)) = name.split_once(ENV_SEPARATOR) else {
+            bail!("{var} should be named {ENV_PREFIX}<SECTION>{ENV_SEPARATOR}<KEY>");
+        };
+        let key = format!("{}.{}", section.to_lowercase(), key.to_lowercase());
+        set(&mut layer, &key, env_value(value));
+    }
+
+    Ok(layer)
+}
+
+fn env_value(value: String) -> Value {
+    toml::from_str::<Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or(Value::String(value))
+}
+
+fn path_value(path: PathBuf) -> Value {
+    Value::String(path.to_string_lossy().into_owned())
+}
+
+/// Set a dotted key such as `database.url`, creating tables along the way
+fn set(table: &mut Table, key: &str, value: Value) {
+    let (sections, last) = match key.rsplit_once('.') {
+        Some((sections, last)) => (sections.split('.').collect(), last),
+        None => (Vec::new(), key),
+    };
+
+    let mut table = table;
+    for section in sections {
+        let entry = table
+            .entry(section)
+            .or_insert_with(|| Value::Table(Table::new()));
+        if !entry.is_table() {
+            *entry = Value::Table(Table::new());
+        }
+        table = entry.as_table_mut().expect("entry was just made a table");
+    }
+    table.insert(last.to_owned(), value);
+}
+
+/// Recursively overlay `top` onto `base`, with values in `top` winning
+fn merge(base: &mut Table, top: Table) {
+    for (key, value) in top {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(top)) => merge(base, top),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderValue, StatusCode},
+    response::{IntoResponseParts, ResponseParts},
+};
+use std::convert::Infallible;
+
+/// Row version of a resource, sent as a strong `ETag`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ETag(pub i32);
+
+impl IntoResponseParts for ETag {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.headers_mut().insert(
+            header::ETAG,
+            HeaderValue::from_str(&format!("\"{}\"", self.0)).expect("ETag is a valid header"),
+        );
+
+        Ok(res)
+    }
+}
+
+/// Precondition from the `If-Match` header of a request
+///
+/// Only a single strong entity tag, as sent in [`ETag`], or `*` are understood.
+/// Anything else can never match the current version of a resource.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IfMatch {
+    Absent,
+    Any,
+    Version(i32),
+    Unmatchable,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for IfMatch {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(header::IF_MATCH) else {
+            return Ok(Self::Absent);
+        };
+
+        let res = match value.to_str().map(str::trim) {
+            Ok("*") => Self::Any,
+            Ok(tag) => tag
+                .strip_prefix('"')
+                .and_then(|tag| tag.strip_suffix('"'))
+                .and_then(|version| version.parse().ok())
+                .map_or(Self::Unmatchable, Self::Version),
+            Err(_) => Self::Unmatchable,
+        };
+
+        Ok(res)
+    }
+}
+
+impl IfMatch {
+    /// Version the client expects the resource to be at, or `None` if any version will do
+    pub fn expected(self) -> Result<Option<i32>, StatusCode> {
+        match self {
+            Self::Absent | Self::Any => Ok(None),
+            Self::Version(version) => Ok(Some(version)),
+            Self::Unmatchable => Err(StatusCode::PRECONDITION_FAILED),
+        }
+    }
+
+    /// Like [`IfMatch::expected`], but rejecting requests without the header
+    pub fn required(self) -> Result<Option<i32>, StatusCode> {
+        match self {
+            Self::Absent => Err(StatusCode::PRECONDITION_REQUIRED),
+            _ => self.expected(),
+        }
+    }
+}
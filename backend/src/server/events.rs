@@ -0,0 +1,104 @@
+use crate::shutdown::Shutdown;
+use anyhow::{bail, Context, Result};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast;
+use tokio_postgres::{config::SslMode, AsyncMessage};
+use tokio_postgres_rustls::MakeRustlsConnect;
+use utoipa::ToSchema;
+
+/// Postgres channel the `notify_report_event` trigger publishes to
+const CHANNEL: &str = "report_events";
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Events buffered per subscriber before slow subscribers start missing them
+pub const CAPACITY: usize = 256;
+
+/// Change to a report or one of its line items, proof, access grants or comments
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ReportEvent {
+    pub report_id: i64,
+    pub entity: String,
+    pub entity_id: i64,
+    pub action: String,
+    pub actor_id: Option<i64>,
+}
+
+/// Forward report events from Postgres to every subscriber of `sender`
+///
+/// Uses a dedicated connection outside the pool, since it stays in `LISTEN` for the
+/// lifetime of the server. Reconnects after errors, so events published while
+/// disconnected are missed.
+///
+/// With `sslmode=require` the connection uses TLS, verified against the Mozilla root
+/// certificates. Other modes connect without TLS.
+pub async fn run(sender: broadcast::Sender<ReportEvent>, database_url: String, shutdown: Shutdown) {
+    let listener = async {
+        loop {
+            if let Err(e) = listen(&sender, &database_url).await {
+                log::error!("Report event listener failed: {e:#}");
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    };
+
+    tokio::select! {
+        _ = listener => {}
+        _ = shutdown.triggered() => log::info!("Stopped report event listener"),
+    }
+}
+
+async fn listen(sender: &broadcast::Sender<ReportEvent>, database_url: &str) -> Result<()> {
+    let mut config: tokio_postgres::Config = database_url
+        .parse()
+        .context("Invalid database url for report events")?;
+    // `prefer` would otherwise try TLS and fail on servers with self-signed certificates
+    if config.get_ssl_mode() != SslMode::Require {
+        config.ssl_mode(SslMode::Disable);
+    }
+    let (client, mut connection) = config
+        .connect(tls()?)
+        .await
+        .context("Unable to connect to database for report events")?;
+    let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+
+    let statement = format!("LISTEN {CHANNEL}");
+    let subscribe = client.batch_execute(&statement);
+    tokio::pin!(subscribe);
+    let mut subscribed = false;
+
+    loop {
+        tokio::select! {
+            res = &mut subscribe, if !subscribed => {
+                res?;
+                subscribed = true;
+                log::info!("Listening for report events");
+            }
+            message = messages.next() => match message {
+                Some(Ok(AsyncMessage::Notification(notification))) => {
+                    match serde_json::from_str::<ReportEvent>(notification.payload()) {
+                        // Only fails when nobody is subscribed
+                        Ok(event) => _ = sender.send(event),
+                        Err(e) => log::warn!("Ignoring malformed report event: {e}"),
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => bail!("Report event connection closed"),
+            }
+        }
+    }
+}
+
+fn tls() -> Result<MakeRustlsConnect> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(MakeRustlsConnect::new(config))
+}
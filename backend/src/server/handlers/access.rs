@@ -1,20 +1,39 @@
-use crate::AppState;
+use crate::openapi::{
+    DatabaseError, DatabaseUnavailable, PatchRejected, PreconditionFailed, PreconditionRequired,
+};
+use crate::{Actor, AppState, ETag, IfMatch};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     response::Result,
     Json,
 };
-use expenser::{NewReportAccess, ReportAccess};
+use expenser::{InvalidPatch, NewReportAccess, ReportAccess, VersionConflict};
+use serde_json::Value;
 
+/// Grant a user access to a report
+///
+/// The report is the one named in the body.
+#[utoipa::path(
+    post,
+    path = "/reports/{report_id}/access",
+    tag = "access",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    request_body = NewReportAccess,
+    responses(
+        (status = 200, description = "Access granted", body = ReportAccess, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn create_access(
     State(state): State<AppState>,
+    Actor(actor): Actor,
     Json(payload): Json<NewReportAccess>,
-) -> Result<Json<ReportAccess>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match payload.insert(database_connection) {
+) -> Result<(ETag, Json<ReportAccess>), StatusCode> {
+    let res = match state.run(move |conn| payload.insert(actor, conn)).await? {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -22,17 +41,30 @@ pub async fn create_access(
         }
     };
 
-    Ok(Json(res))
+    Ok((ETag(res.version), Json(res)))
 }
 
+/// List access grants to a report
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/access",
+    tag = "access",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "Access grants to the report", body = [ReportAccess]),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
 #[axum::debug_handler]
 pub async fn get_access_by_report(
     Path(path): Path<i64>,
     State(state): State<AppState>,
 ) -> Result<Json<Vec<ReportAccess>>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match ReportAccess::get_by_report(path, database_connection) {
+    let res = match state
+        .run(move |conn| ReportAccess::get_by_report(path, conn))
+        .await?
+    {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -43,14 +75,30 @@ pub async fn get_access_by_report(
     Ok(Json(res))
 }
 
+/// Get an access grant
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/access/{id}",
+    tag = "access",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the access grant"),
+    ),
+    responses(
+        (status = 200, description = "The access grant", body = ReportAccess, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
 #[axum::debug_handler]
 pub async fn get_access(
     Path(path): Path<(i64, i64)>,
     State(state): State<AppState>,
-) -> Result<Json<ReportAccess>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match ReportAccess::get_by_path(path, database_connection) {
+) -> Result<(ETag, Json<ReportAccess>), StatusCode> {
+    let res = match state
+        .run(move |conn| ReportAccess::get_by_path(path, conn))
+        .await?
+    {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -58,37 +106,145 @@ pub async fn get_access(
         }
     };
 
-    Ok(Json(res))
+    Ok((ETag(res.version), Json(res)))
 }
 
+/// Replace an access grant
+#[utoipa::path(
+    put,
+    path = "/reports/{report_id}/access/{id}",
+    tag = "access",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the access grant"),
+        ("If-Match" = String, Header, description = "Current `ETag` of the resource"),
+    ),
+    request_body = NewReportAccess,
+    responses(
+        (status = 200, description = "Access grant updated", body = ReportAccess, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 412, response = PreconditionFailed),
+        (status = 428, response = PreconditionRequired),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn update_access(
     Path(path): Path<(i64, i64)>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
+    if_match: IfMatch,
     Json(payload): Json<NewReportAccess>,
-) -> Result<Json<ReportAccess>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
+) -> Result<(ETag, Json<ReportAccess>), StatusCode> {
+    let expected_version = if_match.required()?;
+    let res = match state
+        .run(move |conn| ReportAccess::replace(path, &payload, expected_version, actor, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) if e.is::<VersionConflict>() => {
+            log::info!("{e}");
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok((ETag(res.version), Json(res)))
+}
 
-    let res = match ReportAccess::replace(path, &payload, database_connection) {
+/// Partially update an access grant
+///
+/// Applies a JSON Merge Patch, only `read_access` and `write_access` can be changed.
+#[utoipa::path(
+    patch,
+    path = "/reports/{report_id}/access/{id}",
+    tag = "access",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the access grant"),
+        ("If-Match" = String, Header, description = "Current `ETag` of the resource"),
+    ),
+    request_body = ReportAccessPatch,
+    responses(
+        (status = 200, description = "Access grant updated", body = ReportAccess, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 412, response = PreconditionFailed),
+        (status = 428, response = PreconditionRequired),
+        (status = 422, response = PatchRejected),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn patch_access(
+    Path(path): Path<(i64, i64)>,
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+    if_match: IfMatch,
+    Json(payload): Json<Value>,
+) -> Result<(ETag, Json<ReportAccess>), StatusCode> {
+    let expected_version = if_match.required()?;
+    let res = match state
+        .run(move |conn| ReportAccess::patch(path, &payload, expected_version, actor, conn))
+        .await?
+    {
         Ok(res) => res,
+        Err(e) if e.is::<VersionConflict>() => {
+            log::info!("{e}");
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
+        Err(e) if e.is::<InvalidPatch>() => {
+            log::info!("{e}");
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
         Err(e) => {
             log::error!("{e}");
             return Err(StatusCode::BAD_GATEWAY);
         }
     };
 
-    Ok(Json(res))
+    Ok((ETag(res.version), Json(res)))
 }
 
+/// Revoke an access grant
+#[utoipa::path(
+    delete,
+    path = "/reports/{report_id}/access/{id}",
+    tag = "access",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the access grant"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` the resource must still have to be deleted"),
+    ),
+    responses(
+        (status = 200, description = "Access grant revoked", body = ReportAccess),
+        (status = 412, response = PreconditionFailed),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn delete_access(
     Path(path): Path<(i64, i64)>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
+    if_match: IfMatch,
 ) -> Result<Json<ReportAccess>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match ReportAccess::delete(path, database_connection) {
+    let expected_version = if_match.expected()?;
+    let res = match state
+        .run(move |conn| ReportAccess::delete(path, expected_version, actor, conn))
+        .await?
+    {
         Ok(res) => res,
+        Err(e) if e.is::<VersionConflict>() => {
+            log::info!("{e}");
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
         Err(e) => {
             log::error!("{e}");
             return Err(StatusCode::BAD_GATEWAY);
@@ -98,14 +254,29 @@ pub async fn delete_access(
     Ok(Json(res))
 }
 
+/// Revoke every access grant to a report
+#[utoipa::path(
+    delete,
+    path = "/reports/{report_id}/access",
+    tag = "access",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "Access grants revoked"),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn clear_access(
     Path(path): Path<i64>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
 ) -> Result<(), StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    if let Err(e) = ReportAccess::clear_by_report(path, database_connection) {
+    if let Err(e) = state
+        .run(move |conn| ReportAccess::clear_by_report(path, actor, conn))
+        .await?
+    {
         log::error!("{e}");
         Err(StatusCode::BAD_GATEWAY)
     } else {
@@ -0,0 +1,82 @@
+use super::permissions::require_admin;
+use super::types::LogLevelsSerde;
+use crate::logger::{self, Levels};
+use crate::openapi::{DatabaseError, DatabaseUnavailable, NotAdmin, Unauthenticated};
+use crate::{Actor, AppState};
+use axum::{extract::State, http::StatusCode, response::Result, Json};
+use log::LevelFilter;
+use std::str::FromStr;
+
+fn to_serde(levels: Levels) -> LogLevelsSerde {
+    LogLevelsSerde {
+        default: levels.default.as_str().to_lowercase(),
+        modules: levels
+            .modules
+            .into_iter()
+            .map(|(module, level)| (module, level.as_str().to_lowercase()))
+            .collect(),
+    }
+}
+
+/// Get the current log levels
+#[utoipa::path(
+    get,
+    path = "/admin/log-levels",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The log levels", body = LogLevelsSerde),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotAdmin),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_log_levels(
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<LogLevelsSerde>, StatusCode> {
+    require_admin(&state, actor).await?;
+
+    Ok(Json(to_serde(logger::levels())))
+}
+
+/// Replace the log levels until the next restart, the config file is not changed
+#[utoipa::path(
+    put,
+    path = "/admin/log-levels",
+    tag = "admin",
+    request_body = LogLevelsSerde,
+    responses(
+        (status = 200, description = "Log levels changed", body = LogLevelsSerde),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotAdmin),
+        (status = 422, description = "A level is not one of `off`, `error`, `warn`, `info`, `debug` or `trace`"),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn update_log_levels(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(body): Json<LogLevelsSerde>,
+) -> Result<Json<LogLevelsSerde>, StatusCode> {
+    let user_id = require_admin(&state, actor).await?;
+
+    let default = LevelFilter::from_str(&body.default).or(Err(StatusCode::UNPROCESSABLE_ENTITY))?;
+    let modules = body
+        .modules
+        .into_iter()
+        .map(|(module, level)| Ok((module, LevelFilter::from_str(&level)?)))
+        .collect::<Result<_, log::ParseLevelError>>()
+        .or(Err(StatusCode::UNPROCESSABLE_ENTITY))?;
+
+    let levels = Levels { default, modules };
+    logger::set_levels(levels.clone());
+    log::warn!("User {user_id} changed log levels");
+
+    Ok(Json(to_serde(levels)))
+}
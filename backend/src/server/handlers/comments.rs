@@ -0,0 +1,288 @@
+use super::permissions::require_read_access;
+use super::types::CommentBody;
+use crate::openapi::{DatabaseError, DatabaseUnavailable, Forbidden, Unauthenticated};
+use crate::{Actor, AppState};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Result,
+    Json,
+};
+use expenser::{NewReportComment, ReportComment};
+
+/// Resolve the acting user, requiring them to be the author of the comment
+async fn require_author(
+    state: &AppState,
+    actor: Actor,
+    path: (i64, i64),
+) -> Result<i64, StatusCode> {
+    let user_id = require_read_access(state, actor, path.0).await?;
+
+    match state
+        .run(move |conn| ReportComment::get_by_path(path, conn))
+        .await?
+    {
+        Ok(comment) if comment.author_id == user_id => Ok(user_id),
+        Ok(_) => Err(StatusCode::FORBIDDEN),
+        Err(e) => {
+            log::error!("{e}");
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+/// Comment on a report or one of its line items
+///
+/// The author is the acting user, who needs read access to the report.
+#[utoipa::path(
+    post,
+    path = "/reports/{report_id}/comments",
+    tag = "comments",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    request_body = CommentBody,
+    responses(
+        (status = 200, description = "Comment created", body = ReportComment),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = Forbidden),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn create_comment(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<CommentBody>,
+) -> Result<Json<ReportComment>, StatusCode> {
+    let author_id = require_read_access(&state, actor, path).await?;
+
+    let new = NewReportComment {
+        report_id: path,
+        line_item_id: payload.line_item_id,
+        author_id,
+        body: payload.body,
+    };
+    let res = match state
+        .run(move |conn| new.insert(Some(author_id), conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// List the comments on a report and its line items
+///
+/// Requires read access to the report.
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/comments",
+    tag = "comments",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "Comments on the report", body = [ReportComment]),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = Forbidden),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_comments_by_report(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<Vec<ReportComment>>, StatusCode> {
+    require_read_access(&state, actor, path).await?;
+
+    let res = match state
+        .run(move |conn| ReportComment::get_by_report(path, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// List the comments on a line item
+///
+/// Requires read access to the report.
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/items/{id}/comments",
+    tag = "comments",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the line item"),
+    ),
+    responses(
+        (status = 200, description = "Comments on the line item", body = [ReportComment]),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = Forbidden),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_comments_by_line_item(
+    Path(path): Path<(i64, i64)>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<Vec<ReportComment>>, StatusCode> {
+    require_read_access(&state, actor, path.0).await?;
+
+    let res = match state
+        .run(move |conn| ReportComment::get_by_line_item(path, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Get a comment
+///
+/// Requires read access to the report.
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/comments/{id}",
+    tag = "comments",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the comment"),
+    ),
+    responses(
+        (status = 200, description = "The comment", body = ReportComment),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = Forbidden),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_comment(
+    Path(path): Path<(i64, i64)>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<ReportComment>, StatusCode> {
+    require_read_access(&state, actor, path.0).await?;
+
+    let res = match state
+        .run(move |conn| ReportComment::get_by_path(path, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Edit a comment
+///
+/// Only the body can be changed, and only by the author of the comment.
+#[utoipa::path(
+    put,
+    path = "/reports/{report_id}/comments/{id}",
+    tag = "comments",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the comment"),
+    ),
+    request_body = CommentBody,
+    responses(
+        (status = 200, description = "Comment updated", body = ReportComment),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = Forbidden),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn update_comment(
+    Path(path): Path<(i64, i64)>,
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<CommentBody>,
+) -> Result<Json<ReportComment>, StatusCode> {
+    let author_id = require_author(&state, actor, path).await?;
+
+    let res = match state
+        .run(move |conn| ReportComment::update_body(path, author_id, &payload.body, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Delete a comment
+///
+/// Only the author of the comment may delete it.
+#[utoipa::path(
+    delete,
+    path = "/reports/{report_id}/comments/{id}",
+    tag = "comments",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the comment"),
+    ),
+    responses(
+        (status = 200, description = "Comment deleted", body = ReportComment),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = Forbidden),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn delete_comment(
+    Path(path): Path<(i64, i64)>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<ReportComment>, StatusCode> {
+    let author_id = require_author(&state, actor, path).await?;
+
+    let res = match state
+        .run(move |conn| ReportComment::delete(path, author_id, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
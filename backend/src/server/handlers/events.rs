@@ -0,0 +1,61 @@
+use super::permissions::require_read_access;
+use crate::openapi::{DatabaseError, DatabaseUnavailable, Forbidden, Unauthenticated};
+use crate::{events::ReportEvent, Actor, AppState};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Result,
+    },
+};
+use futures_util::{Stream, StreamExt};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+
+/// Stream changes to a report as Server-Sent Events, named after the changed entity
+///
+/// Subscribers that fall behind receive a `lagged` event with the number of missed
+/// events, and should refetch the report. Streams end when the server shuts down.
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/events",
+    tag = "reports",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "Stream of events, each carrying a `ReportEvent` as its data", body = ReportEvent, content_type = "text/event-stream"),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = Forbidden),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_report_events(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Sse<impl Stream<Item = Result<Event, serde_json::Error>>>, StatusCode> {
+    require_read_access(&state, actor, path).await?;
+
+    let events = BroadcastStream::new(state.report_events().subscribe())
+        .filter_map(move |res| async move {
+            match res {
+                Ok(event) if event.report_id == path => Some(event_to_sse(&event)),
+                Ok(_) => None,
+                Err(BroadcastStreamRecvError::Lagged(missed)) => Some(Ok(Event::default()
+                    .event("lagged")
+                    .data(missed.to_string()))),
+            }
+        })
+        .take_until(state.shutdown().triggered());
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+fn event_to_sse(event: &ReportEvent) -> Result<Event, serde_json::Error> {
+    Event::default()
+        .event(&event.entity)
+        .id(event.entity_id.to_string())
+        .json_data(event)
+}
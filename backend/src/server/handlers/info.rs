@@ -1,18 +1,107 @@
-use super::types::CrateInfo;
-use axum::Json;
+use super::types::{ComponentStatus, CrateInfo, MigrationStatus, Readiness, ReadinessStatus};
+use crate::AppState;
+use axum::{extract::State, http::StatusCode, Json};
+use std::time::Duration;
 
-/// Healthcheck handler
+/// How long the readiness check waits for a pooled connection
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Check the server is alive
 ///
-/// Returns `Healthy!` if healthy
+/// Returns `Healthy!` while the process is able to serve requests at all, without
+/// looking at the database
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses(
+        (status = 200, description = "Alive", body = String, example = json!("Healthy!")),
+    )
+)]
 pub(crate) async fn health() -> &'static str {
     log::info!("Request made to health endpoint");
 
     "Healthy!"
 }
 
-/// Crate information handler used to get information on the server
+/// Check the server is alive
+///
+/// Older path of `/health/live`, kept for existing health checks
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "health",
+    responses(
+        (status = 200, description = "Alive", body = String, example = json!("Healthy!")),
+    )
+)]
+pub(crate) async fn legacy_health() -> &'static str {
+    health().await
+}
+
+/// Check the server is ready to serve traffic
 ///
-/// Uses [`CrateInfo`] struct
+/// Checks a pooled connection can be acquired and answers a query, and reports whether
+/// migrations are pending. Responds with 503 when not ready or while shutting down.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready to serve traffic", body = Readiness),
+        (status = 503, description = "Not ready, or shutting down", body = Readiness),
+    )
+)]
+pub(crate) async fn ready(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    log::info!("Request made to readiness endpoint");
+
+    let pool = state.pool().clone();
+    let check =
+        tokio::task::spawn_blocking(move || expenser::database::check(&pool, READINESS_TIMEOUT))
+            .await;
+
+    let (database, migrations) = match check {
+        Ok(Ok(false)) => (ComponentStatus::Ok, MigrationStatus::UpToDate),
+        Ok(Ok(true)) => (ComponentStatus::Ok, MigrationStatus::Pending),
+        Ok(Err(e)) => {
+            log::warn!("Readiness check failed: {e:#}");
+            (ComponentStatus::Unavailable, MigrationStatus::Unknown)
+        }
+        Err(e) => {
+            log::error!("{e}");
+            (ComponentStatus::Unavailable, MigrationStatus::Unknown)
+        }
+    };
+    let status = match (&database, &migrations) {
+        _ if state.shutdown().is_triggered() => ReadinessStatus::Draining,
+        (ComponentStatus::Ok, MigrationStatus::UpToDate) => ReadinessStatus::Ready,
+        _ => ReadinessStatus::NotReady,
+    };
+
+    let code = match status {
+        ReadinessStatus::Ready => StatusCode::OK,
+        _ => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (
+        code,
+        Json(Readiness {
+            status,
+            database,
+            migrations,
+        }),
+    )
+}
+
+/// Get the name, version and authors of the server
+#[utoipa::path(
+    get,
+    path = "/info",
+    tag = "health",
+    responses(
+        (status = 200, description = "Name, version and other details of the server", body = CrateInfo),
+    )
+)]
 pub(crate) async fn info() -> Json<CrateInfo> {
     log::info!("Request made to info endpoint");
 
@@ -1,21 +1,47 @@
-use super::types::{NewReportLineItemSerde, ReportLineItemSerde};
-use crate::AppState;
+use super::permissions::require_write_access;
+use super::types::{BulkResult, MoveLineItems};
+use crate::openapi::{
+    DatabaseError, DatabaseUnavailable, Forbidden, PatchRejected, PreconditionFailed,
+    PreconditionRequired, Unauthenticated,
+};
+use crate::{Actor, ApiVersion, AppState, ETag, IfMatch, VersionedJson};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     response::Result,
     Json,
 };
-use expenser::{NewReportLineItem, ReportLineItem};
+use expenser::{
+    BulkOperationFailed, InvalidPatch, LineItemOperation, NewReportLineItem, ReportLineItem,
+    VersionConflict,
+};
+use serde_json::Value;
 
+/// Largest number of operations accepted in one bulk request
+const MAX_BULK_OPERATIONS: usize = 500;
+
+/// Add a line item to a report
+///
+/// The report is the one named in the body.
+#[utoipa::path(
+    post,
+    path = "/reports/{report_id}/items",
+    tag = "line_items",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    request_body = NewReportLineItemSerde,
+    responses(
+        (status = 200, description = "Line item created", body = ReportLineItemSerde, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 pub async fn create_line_item<'a>(
     State(state): State<AppState>,
-    Json(payload): Json<NewReportLineItemSerde>,
-) -> Result<Json<ReportLineItemSerde>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let non_serde_payload: NewReportLineItem = payload.into();
-    let res = match non_serde_payload.insert(database_connection) {
+    Actor(actor): Actor,
+    VersionedJson(version, payload): VersionedJson<NewReportLineItem>,
+) -> Result<(ETag, VersionedJson<ReportLineItem>), StatusCode> {
+    let res = match state.run(move |conn| payload.insert(actor, conn)).await? {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -23,36 +49,66 @@ pub async fn create_line_item<'a>(
         }
     };
 
-    Ok(Json(res.into()))
+    Ok((ETag(res.version), VersionedJson(version, res)))
 }
 
+/// List the line items of a report
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/items",
+    tag = "line_items",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "Line items of the report", body = [ReportLineItemSerde]),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
 #[axum::debug_handler]
 pub async fn get_line_items_by_report(
     Path(path): Path<i64>,
     State(state): State<AppState>,
-) -> Result<Json<Vec<ReportLineItemSerde>>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match ReportLineItem::get_by_report(path, database_connection) {
+    version: ApiVersion,
+) -> Result<VersionedJson<Vec<ReportLineItem>>, StatusCode> {
+    let res = match state
+        .run(move |conn| ReportLineItem::get_by_report(path, conn))
+        .await?
+    {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
             return Err(StatusCode::BAD_GATEWAY);
         }
     };
-    let serde_res: Vec<ReportLineItemSerde> = res.into_iter().map(|i| i.into()).collect();
 
-    Ok(Json(serde_res))
+    Ok(VersionedJson(version, res))
 }
 
+/// Get a line item
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/items/{id}",
+    tag = "line_items",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the line item"),
+    ),
+    responses(
+        (status = 200, description = "The line item", body = ReportLineItemSerde, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
 #[axum::debug_handler]
 pub async fn get_line_item(
     Path(path): Path<(i64, i64)>,
     State(state): State<AppState>,
-) -> Result<Json<ReportLineItemSerde>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match ReportLineItem::get_by_path(path, database_connection) {
+    version: ApiVersion,
+) -> Result<(ETag, VersionedJson<ReportLineItem>), StatusCode> {
+    let res = match state
+        .run(move |conn| ReportLineItem::get_by_path(path, conn))
+        .await?
+    {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -60,55 +116,316 @@ pub async fn get_line_item(
         }
     };
 
-    Ok(Json(res.into()))
+    Ok((ETag(res.version), VersionedJson(version, res)))
 }
+/// Replace a line item
+#[utoipa::path(
+    put,
+    path = "/reports/{report_id}/items/{id}",
+    tag = "line_items",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the line item"),
+        ("If-Match" = String, Header, description = "Current `ETag` of the resource"),
+    ),
+    request_body = NewReportLineItemSerde,
+    responses(
+        (status = 200, description = "Line item updated", body = ReportLineItemSerde, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 412, response = PreconditionFailed),
+        (status = 428, response = PreconditionRequired),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 pub async fn update_line_item<'a>(
     Path(path): Path<(i64, i64)>,
     State(state): State<AppState>,
-    Json(payload): Json<NewReportLineItemSerde>,
-) -> Result<Json<ReportLineItemSerde>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
+    Actor(actor): Actor,
+    if_match: IfMatch,
+    VersionedJson(version, payload): VersionedJson<NewReportLineItem>,
+) -> Result<(ETag, VersionedJson<ReportLineItem>), StatusCode> {
+    let expected_version = if_match.required()?;
+    let res = match state
+        .run(move |conn| ReportLineItem::replace(path, &payload, expected_version, actor, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) if e.is::<VersionConflict>() => {
+            log::info!("{e}");
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
 
-    let res = match ReportLineItem::replace(path, &payload.into(), database_connection) {
+    Ok((ETag(res.version), VersionedJson(version, res)))
+}
+
+/// Partially update a line item
+///
+/// Applies a JSON Merge Patch, only `item_name` and `item_price_usd` can be changed.
+#[utoipa::path(
+    patch,
+    path = "/reports/{report_id}/items/{id}",
+    tag = "line_items",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the line item"),
+        ("If-Match" = String, Header, description = "Current `ETag` of the resource"),
+    ),
+    request_body = ReportLineItemPatch,
+    responses(
+        (status = 200, description = "Line item updated", body = ReportLineItemSerde, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 412, response = PreconditionFailed),
+        (status = 428, response = PreconditionRequired),
+        (status = 422, response = PatchRejected),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn patch_line_item(
+    Path(path): Path<(i64, i64)>,
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+    if_match: IfMatch,
+    version: ApiVersion,
+    Json(payload): Json<Value>,
+) -> Result<(ETag, VersionedJson<ReportLineItem>), StatusCode> {
+    let expected_version = if_match.required()?;
+    let res = match state
+        .run(move |conn| ReportLineItem::patch(path, &payload, expected_version, actor, conn))
+        .await?
+    {
         Ok(res) => res,
+        Err(e) if e.is::<VersionConflict>() => {
+            log::info!("{e}");
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
+        Err(e) if e.is::<InvalidPatch>() => {
+            log::info!("{e}");
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
         Err(e) => {
             log::error!("{e}");
             return Err(StatusCode::BAD_GATEWAY);
         }
     };
 
-    Ok(Json(res.into()))
+    Ok((ETag(res.version), VersionedJson(version, res)))
 }
 
+/// Delete a line item
+#[utoipa::path(
+    delete,
+    path = "/reports/{report_id}/items/{id}",
+    tag = "line_items",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the line item"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` the resource must still have to be deleted"),
+    ),
+    responses(
+        (status = 200, description = "Line item deleted", body = ReportLineItemSerde),
+        (status = 412, response = PreconditionFailed),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn delete_line_item(
     Path(path): Path<(i64, i64)>,
     State(state): State<AppState>,
-) -> Result<Json<ReportLineItemSerde>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match ReportLineItem::delete(path, database_connection) {
+    Actor(actor): Actor,
+    if_match: IfMatch,
+    version: ApiVersion,
+) -> Result<VersionedJson<ReportLineItem>, StatusCode> {
+    let expected_version = if_match.expected()?;
+    let res = match state
+        .run(move |conn| ReportLineItem::delete(path, expected_version, actor, conn))
+        .await?
+    {
         Ok(res) => res,
+        Err(e) if e.is::<VersionConflict>() => {
+            log::info!("{e}");
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
         Err(e) => {
             log::error!("{e}");
             return Err(StatusCode::BAD_GATEWAY);
         }
     };
 
-    Ok(Json(res.into()))
+    Ok(VersionedJson(version, res))
 }
 
+/// Delete every line item of a report
+#[utoipa::path(
+    delete,
+    path = "/reports/{report_id}/items",
+    tag = "line_items",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "Line items deleted"),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn clear_line_items(
     Path(path): Path<i64>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
 ) -> Result<(), StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    if let Err(e) = ReportLineItem::clear_by_report(path, database_connection) {
+    if let Err(e) = state
+        .run(move |conn| ReportLineItem::clear_by_report(path, actor, conn))
+        .await?
+    {
         log::error!("{e}");
         Err(StatusCode::BAD_GATEWAY)
     } else {
         Ok(())
     }
 }
+
+/// Create, update and delete several line items at once
+///
+/// Operations are applied in order in a single transaction, so either all of them take
+/// effect or none do. The response lists the outcome of each operation in request order.
+/// When one fails, earlier operations are `rolled_back`, later ones `skipped`, and the
+/// status is that of the failure.
+#[utoipa::path(
+    post,
+    path = "/reports/{report_id}/items/bulk",
+    tag = "line_items",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    request_body = [LineItemOperationSerde],
+    responses(
+        (status = 200, description = "Every operation was applied", body = [BulkResultSerde]),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = Forbidden),
+        (status = 412, description = "An operation's `version` did not match the line item", body = [BulkResultSerde]),
+        (status = 413, description = "More than 500 operations"),
+        (status = 422, description = "An operation named a line item which is not in the report", body = [BulkResultSerde]),
+        (status = 502, description = "An operation failed in the database", body = [BulkResultSerde]),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn bulk_line_items(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+    VersionedJson(version, operations): VersionedJson<Vec<LineItemOperation>>,
+) -> Result<(StatusCode, VersionedJson<Vec<BulkResult>>), StatusCode> {
+    if operations.len() > MAX_BULK_OPERATIONS {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let actor_id = require_write_access(&state, actor, path).await?;
+
+    let count = operations.len();
+    let res = state
+        .run(move |conn| ReportLineItem::apply_bulk(path, &operations, Some(actor_id), conn))
+        .await?;
+
+    bulk_response(version, count, res)
+}
+
+/// Move line items to another report
+///
+/// Items are moved in a single transaction, and results are reported as for bulk
+/// operations in the order of `item_ids`. Comments on the items move with them. The old
+/// report's history records each item as deleted. Requires write access to both reports.
+#[utoipa::path(
+    post,
+    path = "/reports/{report_id}/items/move",
+    tag = "line_items",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    request_body = MoveLineItems,
+    responses(
+        (status = 200, description = "Every item was moved", body = [BulkResultSerde]),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = Forbidden),
+        (status = 413, description = "More than 500 items"),
+        (status = 422, description = "An item is not in the report", body = [BulkResultSerde]),
+        (status = 502, description = "Moving an item failed in the database", body = [BulkResultSerde]),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn move_line_items(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+    version: ApiVersion,
+    Json(payload): Json<MoveLineItems>,
+) -> Result<(StatusCode, VersionedJson<Vec<BulkResult>>), StatusCode> {
+    if payload.item_ids.len() > MAX_BULK_OPERATIONS {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let actor_id = require_write_access(&state, actor, path).await?;
+    require_write_access(&state, actor, payload.to_report_id).await?;
+
+    let count = payload.item_ids.len();
+    let res = state
+        .run(move |conn| {
+            ReportLineItem::move_to_report(
+                path,
+                &payload.item_ids,
+                payload.to_report_id,
+                Some(actor_id),
+                conn,
+            )
+        })
+        .await?;
+
+    bulk_response(version, count, res)
+}
+
+/// Per-item results of a bulk request, with the status of the operation that failed, if any
+fn bulk_response(
+    version: ApiVersion,
+    count: usize,
+    res: anyhow::Result<Vec<ReportLineItem>>,
+) -> Result<(StatusCode, VersionedJson<Vec<BulkResult>>), StatusCode> {
+    let e = match res {
+        Ok(items) => {
+            return Ok((
+                StatusCode::OK,
+                VersionedJson(version, BulkResult::applied(items)),
+            ))
+        }
+        Err(e) => e,
+    };
+    let Some(failure) = e.downcast_ref::<BulkOperationFailed>() else {
+        log::error!("{e}");
+        return Err(StatusCode::BAD_GATEWAY);
+    };
+
+    let (status, message) = if failure.error.is::<VersionConflict>() {
+        log::info!("{e}");
+        (StatusCode::PRECONDITION_FAILED, failure.error.to_string())
+    } else if let Some(diesel::result::Error::NotFound) = failure.error.downcast_ref() {
+        log::info!("{e}");
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Line item not found in report".to_owned(),
+        )
+    } else {
+        log::error!("{e}");
+        (StatusCode::BAD_GATEWAY, "Database error".to_owned())
+    };
+
+    Ok((
+        status,
+        VersionedJson(version, BulkResult::failed(count, failure.index, message)),
+    ))
+}
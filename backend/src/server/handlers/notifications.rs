@@ -0,0 +1,166 @@
+use super::permissions::require_self;
+use super::types::{NotificationQuery, UnreadCount};
+use crate::openapi::{DatabaseError, DatabaseUnavailable, NotSelf, Unauthenticated};
+use crate::{Actor, AppState};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Result,
+    Json,
+};
+use expenser::Notification;
+
+/// List a user's notifications
+///
+/// Newest first, only unread notifications are listed unless `all` is set. Only the user
+/// themselves may read their notifications.
+#[utoipa::path(
+    get,
+    path = "/users/{id}/notifications",
+    tag = "notifications",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+        NotificationQuery,
+    ),
+    responses(
+        (status = 200, description = "The user's notifications", body = [Notification]),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotSelf),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_notifications(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+    Query(query): Query<NotificationQuery>,
+) -> Result<Json<Vec<Notification>>, StatusCode> {
+    require_self(actor, path)?;
+
+    let res = match state
+        .run(move |conn| Notification::get_by_user(path, query.all, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Count a user's unread notifications
+#[utoipa::path(
+    get,
+    path = "/users/{id}/notifications/count",
+    tag = "notifications",
+    params(("id" = i64, Path, description = "Id of the user")),
+    responses(
+        (status = 200, description = "Number of unread notifications", body = UnreadCount),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotSelf),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_unread_count(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<UnreadCount>, StatusCode> {
+    require_self(actor, path)?;
+
+    let res = match state
+        .run(move |conn| Notification::count_unread(path, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(UnreadCount { unread: res }))
+}
+
+/// Mark a notification as read
+#[utoipa::path(
+    post,
+    path = "/users/{id}/notifications/{notification_id}/read",
+    tag = "notifications",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+        ("notification_id" = i64, Path, description = "Id of the notification"),
+    ),
+    responses(
+        (status = 200, description = "Notification marked as read", body = Notification),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotSelf),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn mark_notification_read(
+    Path(path): Path<(i64, i64)>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<Notification>, StatusCode> {
+    require_self(actor, path.0)?;
+
+    let res = match state
+        .run(move |conn| Notification::mark_read(path, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Mark all of a user's notifications as read
+#[utoipa::path(
+    post,
+    path = "/users/{id}/notifications/read",
+    tag = "notifications",
+    params(("id" = i64, Path, description = "Id of the user")),
+    responses(
+        (status = 200, description = "Notifications marked as read"),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotSelf),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn mark_all_notifications_read(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<(), StatusCode> {
+    require_self(actor, path)?;
+
+    if let Err(e) = state
+        .run(move |conn| Notification::mark_all_read(path, conn))
+        .await?
+    {
+        log::error!("{e}");
+        Err(StatusCode::BAD_GATEWAY)
+    } else {
+        Ok(())
+    }
+}
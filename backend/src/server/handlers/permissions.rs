@@ -0,0 +1,67 @@
+use crate::{Actor, AppState};
+use axum::http::StatusCode;
+use expenser::{ReportAccess, User};
+
+/// Resolve the acting user, requiring them to have read access to the report
+pub(crate) async fn require_read_access(
+    state: &AppState,
+    actor: Actor,
+    report_id: i64,
+) -> Result<i64, StatusCode> {
+    let user_id = actor.required()?;
+
+    match state
+        .run(move |conn| ReportAccess::can_read(report_id, user_id, conn))
+        .await?
+    {
+        Ok(true) => Ok(user_id),
+        Ok(false) => Err(StatusCode::FORBIDDEN),
+        Err(e) => {
+            log::error!("{e}");
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+/// Resolve the acting user, requiring them to have write access to the report
+pub(crate) async fn require_write_access(
+    state: &AppState,
+    actor: Actor,
+    report_id: i64,
+) -> Result<i64, StatusCode> {
+    let user_id = actor.required()?;
+
+    match state
+        .run(move |conn| ReportAccess::can_write(report_id, user_id, conn))
+        .await?
+    {
+        Ok(true) => Ok(user_id),
+        Ok(false) => Err(StatusCode::FORBIDDEN),
+        Err(e) => {
+            log::error!("{e}");
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+/// Resolve the acting user, requiring them to be an administrator
+pub(crate) async fn require_admin(state: &AppState, actor: Actor) -> Result<i64, StatusCode> {
+    let user_id = actor.required()?;
+
+    match state.run(move |conn| User::is_admin(user_id, conn)).await? {
+        Ok(true) => Ok(user_id),
+        Ok(false) => Err(StatusCode::FORBIDDEN),
+        Err(e) => {
+            log::error!("{e}");
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+/// Resolve the acting user, requiring them to be `user_id`
+pub(crate) fn require_self(actor: Actor, user_id: i64) -> Result<i64, StatusCode> {
+    match actor.required()? {
+        actor_id if actor_id == user_id => Ok(actor_id),
+        _ => Err(StatusCode::FORBIDDEN),
+    }
+}
@@ -1,19 +1,40 @@
-use crate::AppState;
+use crate::openapi::{
+    DatabaseError, DatabaseUnavailable, PreconditionFailed, PreconditionRequired, TooManyRequests,
+    UploadTooLarge,
+};
+use crate::{Actor, AppState, ETag, IfMatch};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     response::Result,
     Json,
 };
-use expenser::{NewReportProof, ReportProof};
-
+use expenser::{NewReportProof, ReportProof, VersionConflict};
+
+/// Attach proof to a report
+///
+/// The report is the one named in the body.
+#[utoipa::path(
+    post,
+    path = "/reports/{report_id}/proof",
+    tag = "proof",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    request_body = NewReportProof,
+    responses(
+        (status = 200, description = "Proof attached", body = ReportProof, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 413, response = UploadTooLarge),
+        (status = 429, response = TooManyRequests),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 pub async fn create_proof<'a>(
     State(state): State<AppState>,
+    Actor(actor): Actor,
     Json(payload): Json<NewReportProof>,
-) -> Result<Json<ReportProof>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match payload.insert(database_connection) {
+) -> Result<(ETag, Json<ReportProof>), StatusCode> {
+    let res = match state.run(move |conn| payload.insert(actor, conn)).await? {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -21,17 +42,30 @@ pub async fn create_proof<'a>(
         }
     };
 
-    Ok(Json(res))
+    Ok((ETag(res.version), Json(res)))
 }
 
+/// List the proof attached to a report
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/proof",
+    tag = "proof",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "Proof attached to the report", body = [ReportProof]),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
 #[axum::debug_handler]
 pub async fn get_proof_by_report(
     Path(path): Path<i64>,
     State(state): State<AppState>,
 ) -> Result<Json<Vec<ReportProof>>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match ReportProof::get_by_report(path, database_connection) {
+    let res = match state
+        .run(move |conn| ReportProof::get_by_report(path, conn))
+        .await?
+    {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -42,14 +76,30 @@ pub async fn get_proof_by_report(
     Ok(Json(res))
 }
 
+/// Get a piece of proof
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/proof/{id}",
+    tag = "proof",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the proof"),
+    ),
+    responses(
+        (status = 200, description = "The proof", body = ReportProof, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
 #[axum::debug_handler]
 pub async fn get_proof(
     Path(path): Path<(i64, i64)>,
     State(state): State<AppState>,
-) -> Result<Json<ReportProof>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match ReportProof::get_by_path(path, database_connection) {
+) -> Result<(ETag, Json<ReportProof>), StatusCode> {
+    let res = match state
+        .run(move |conn| ReportProof::get_by_path(path, conn))
+        .await?
+    {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -57,36 +107,90 @@ pub async fn get_proof(
         }
     };
 
-    Ok(Json(res))
+    Ok((ETag(res.version), Json(res)))
 }
 
+/// Replace a piece of proof
+#[utoipa::path(
+    put,
+    path = "/reports/{report_id}/proof/{id}",
+    tag = "proof",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the proof"),
+        ("If-Match" = String, Header, description = "Current `ETag` of the resource"),
+    ),
+    request_body = NewReportProof,
+    responses(
+        (status = 200, description = "Proof updated", body = ReportProof, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 412, response = PreconditionFailed),
+        (status = 428, response = PreconditionRequired),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 pub async fn update_proof<'a>(
     Path(path): Path<(i64, i64)>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
+    if_match: IfMatch,
     Json(payload): Json<NewReportProof>,
-) -> Result<Json<ReportProof>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match ReportProof::replace(path, &payload, database_connection) {
+) -> Result<(ETag, Json<ReportProof>), StatusCode> {
+    let expected_version = if_match.required()?;
+    let res = match state
+        .run(move |conn| ReportProof::replace(path, &payload, expected_version, actor, conn))
+        .await?
+    {
         Ok(res) => res,
+        Err(e) if e.is::<VersionConflict>() => {
+            log::info!("{e}");
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
         Err(e) => {
             log::error!("{e}");
             return Err(StatusCode::BAD_GATEWAY);
         }
     };
 
-    Ok(Json(res))
+    Ok((ETag(res.version), Json(res)))
 }
 
+/// Delete a piece of proof
+#[utoipa::path(
+    delete,
+    path = "/reports/{report_id}/proof/{id}",
+    tag = "proof",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("id" = i64, Path, description = "Id of the proof"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` the resource must still have to be deleted"),
+    ),
+    responses(
+        (status = 200, description = "Proof deleted", body = ReportProof),
+        (status = 412, response = PreconditionFailed),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn delete_proof(
     Path(path): Path<(i64, i64)>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
+    if_match: IfMatch,
 ) -> Result<Json<ReportProof>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match ReportProof::delete(path, database_connection) {
+    let expected_version = if_match.expected()?;
+    let res = match state
+        .run(move |conn| ReportProof::delete(path, expected_version, actor, conn))
+        .await?
+    {
         Ok(res) => res,
+        Err(e) if e.is::<VersionConflict>() => {
+            log::info!("{e}");
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
         Err(e) => {
             log::error!("{e}");
             return Err(StatusCode::BAD_GATEWAY);
@@ -96,14 +200,30 @@ pub async fn delete_proof(
     Ok(Json(res))
 }
 
+/// Delete all proof attached to a report
+#[utoipa::path(
+    delete,
+    path = "/reports/{report_id}/proof",
+    tag = "proof",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "Proof deleted"),
+        (status = 429, response = TooManyRequests),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn clear_proof(
     Path(path): Path<i64>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
 ) -> Result<(), StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    if let Err(e) = ReportProof::clear_by_report(path, database_connection) {
+    if let Err(e) = state
+        .run(move |conn| ReportProof::clear_by_report(path, actor, conn))
+        .await?
+    {
         log::error!("{e}");
         Err(StatusCode::BAD_GATEWAY)
     } else {
@@ -1,19 +1,39 @@
-use crate::AppState;
+use super::permissions::require_read_access;
+use crate::openapi::{
+    DatabaseError, DatabaseUnavailable, Forbidden, PatchRejected, PreconditionFailed,
+    PreconditionRequired, Unauthenticated,
+};
+use crate::{Actor, AppState, ETag, IfMatch};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     response::Result,
     Json,
 };
-use expenser::{NewReport, Report};
+use expenser::{AuditLog, InvalidPatch, NewReport, Report, VersionConflict};
+use serde_json::Value;
 
+/// Create a report
+///
+/// The acting user, if any, is recorded in the report's history.
+#[utoipa::path(
+    post,
+    path = "/reports",
+    tag = "reports",
+    request_body = NewReport,
+    responses(
+        (status = 200, description = "Report created", body = Report, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 pub async fn create_report<'a>(
     State(state): State<AppState>,
+    Actor(actor): Actor,
     Json(payload): Json<NewReport>,
-) -> Result<Json<Report>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match payload.insert(database_connection) {
+) -> Result<(ETag, Json<Report>), StatusCode> {
+    let res = match state.run(move |conn| payload.insert(actor, conn)).await? {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -21,17 +41,27 @@ pub async fn create_report<'a>(
         }
     };
 
-    Ok(Json(res))
+    Ok((ETag(res.version), Json(res)))
 }
 
+/// Get a report
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}",
+    tag = "reports",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "The report", body = Report, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
 #[axum::debug_handler]
 pub async fn get_report(
     Path(path): Path<i64>,
     State(state): State<AppState>,
-) -> Result<Json<Report>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match Report::get_by_id(path, database_connection) {
+) -> Result<(ETag, Json<Report>), StatusCode> {
+    let res = match state.run(move |conn| Report::get_by_id(path, conn)).await? {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -39,41 +69,190 @@ pub async fn get_report(
         }
     };
 
-    Ok(Json(res))
+    Ok((ETag(res.version), Json(res)))
 }
 
+/// Replace a report
+#[utoipa::path(
+    put,
+    path = "/reports/{report_id}",
+    tag = "reports",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("If-Match" = String, Header, description = "Current `ETag` of the resource"),
+    ),
+    request_body = NewReport,
+    responses(
+        (status = 200, description = "Report updated", body = Report, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 412, response = PreconditionFailed),
+        (status = 428, response = PreconditionRequired),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 pub async fn update_report<'a>(
     Path(path): Path<i64>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
+    if_match: IfMatch,
     Json(payload): Json<NewReport>,
-) -> Result<Json<Report>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
+) -> Result<(ETag, Json<Report>), StatusCode> {
+    let expected_version = if_match.required()?;
+    let res = match state
+        .run(move |conn| {
+            Report::update(
+                path,
+                payload.owner_id,
+                payload.title,
+                payload.description,
+                expected_version,
+                actor,
+                conn,
+            )
+        })
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) if e.is::<VersionConflict>() => {
+            log::info!("{e}");
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
 
-    let res = match Report::update(
-        path,
-        payload.owner_id,
-        payload.title,
-        payload.description,
-        database_connection,
-    ) {
+    Ok((ETag(res.version), Json(res)))
+}
+
+/// Partially update a report
+///
+/// Applies a JSON Merge Patch, only `title` and `description` can be changed.
+#[utoipa::path(
+    patch,
+    path = "/reports/{report_id}",
+    tag = "reports",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("If-Match" = String, Header, description = "Current `ETag` of the resource"),
+    ),
+    request_body = ReportPatch,
+    responses(
+        (status = 200, description = "Report updated", body = Report, headers(("ETag" = String, description = "Version of the resource, to send back in `If-Match`"))),
+        (status = 412, response = PreconditionFailed),
+        (status = 428, response = PreconditionRequired),
+        (status = 422, response = PatchRejected),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn patch_report(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+    if_match: IfMatch,
+    Json(payload): Json<Value>,
+) -> Result<(ETag, Json<Report>), StatusCode> {
+    let expected_version = if_match.required()?;
+    let res = match state
+        .run(move |conn| Report::patch(path, &payload, expected_version, actor, conn))
+        .await?
+    {
         Ok(res) => res,
+        Err(e) if e.is::<VersionConflict>() => {
+            log::info!("{e}");
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
+        Err(e) if e.is::<InvalidPatch>() => {
+            log::info!("{e}");
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
         Err(e) => {
             log::error!("{e}");
             return Err(StatusCode::BAD_GATEWAY);
         }
     };
 
-    Ok(Json(res))
+    Ok((ETag(res.version), Json(res)))
 }
 
+/// Delete a report
+#[utoipa::path(
+    delete,
+    path = "/reports/{report_id}",
+    tag = "reports",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` the resource must still have to be deleted"),
+    ),
+    responses(
+        (status = 200, description = "Report deleted", body = Report),
+        (status = 412, response = PreconditionFailed),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn delete_report(
     Path(path): Path<i64>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
+    if_match: IfMatch,
 ) -> Result<Json<Report>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
+    let expected_version = if_match.expected()?;
+    let res = match state
+        .run(move |conn| Report::delete(path, expected_version, actor, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) if e.is::<VersionConflict>() => {
+            log::info!("{e}");
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Get the audit history of a report
+///
+/// Lists every recorded change to the report, its line items, proof and access grants in
+/// the order they were made. Requires read access to the report.
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/history",
+    tag = "reports",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "Changes to the report", body = [AuditLog]),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = Forbidden),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_report_history(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<Vec<AuditLog>>, StatusCode> {
+    require_read_access(&state, actor, path).await?;
 
-    let res = match Report::delete(path, database_connection) {
+    let res = match state
+        .run(move |conn| AuditLog::get_by_report(path, conn))
+        .await?
+    {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -1,6 +1,8 @@
+use crate::api_version::{FromVersioned, IntoVersioned};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 /// Information on version and other fields set in the cargo manifest
 pub struct CrateInfo {
     pub name: &'static str,
@@ -11,24 +13,58 @@ pub struct CrateInfo {
     pub repository: &'static str,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Debug, Serialize, ToSchema)]
+/// Result of the readiness check, serving traffic only makes sense when `status` is `ready`
+pub struct Readiness {
+    pub status: ReadinessStatus,
+    pub database: ComponentStatus,
+    pub migrations: MigrationStatus,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessStatus {
+    Ready,
+    NotReady,
+    /// Shutting down, in-flight requests are still being finished
+    Draining,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentStatus {
+    Ok,
+    Unavailable,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationStatus {
+    UpToDate,
+    Pending,
+    Unknown,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
 pub struct ReportLineItemSerde {
     id: i64,
     report_id: i64,
     item_name: String,
     item_price_usd: f64,
+    version: i32,
 }
 
 impl From<ReportLineItemSerde> for expenser::ReportLineItem {
     fn from(value: ReportLineItemSerde) -> Self {
         use diesel::data_types::Cents;
-        let price_usd_cents = Cents((value.item_price_usd * 100.0).trunc() as i64);
+        let price_usd_cents = Cents((value.item_price_usd * 100.0).round() as i64);
 
         Self {
             id: value.id,
             report_id: value.report_id,
             item_name: value.item_name,
             item_price_usd: price_usd_cents,
+            version: value.version,
         }
     }
 }
@@ -42,11 +78,20 @@ impl From<expenser::ReportLineItem> for ReportLineItemSerde {
             report_id: value.report_id,
             item_name: value.item_name,
             item_price_usd: price_usd,
+            version: value.version,
         }
     }
 }
 
-#[derive(Deserialize, Debug)]
+impl IntoVersioned for expenser::ReportLineItem {
+    type V1 = ReportLineItemSerde;
+
+    fn into_v1(self) -> Self::V1 {
+        self.into()
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct NewReportLineItemSerde {
     report_id: i64,
     item_name: String,
@@ -56,7 +101,7 @@ pub struct NewReportLineItemSerde {
 impl From<NewReportLineItemSerde> for expenser::NewReportLineItem {
     fn from(value: NewReportLineItemSerde) -> Self {
         use diesel::data_types::Cents;
-        let price_usd_cents = Cents((value.item_price_usd * 100.0).trunc() as i64);
+        let price_usd_cents = Cents((value.item_price_usd * 100.0).round() as i64);
 
         Self {
             report_id: value.report_id,
@@ -77,3 +122,206 @@ impl From<expenser::NewReportLineItem> for NewReportLineItemSerde {
         }
     }
 }
+
+impl FromVersioned for expenser::NewReportLineItem {
+    type V1 = NewReportLineItemSerde;
+
+    fn from_v1(body: Self::V1) -> Self {
+        body.into()
+    }
+}
+
+/// One operation of a bulk line item request, tagged by `op`
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum LineItemOperationSerde {
+    Create {
+        item_name: String,
+        item_price_usd: f64,
+    },
+    Update {
+        id: i64,
+        item_name: String,
+        item_price_usd: f64,
+        /// Version the item is expected to be at, like `If-Match`
+        version: Option<i32>,
+    },
+    Delete {
+        id: i64,
+        version: Option<i32>,
+    },
+}
+
+impl From<LineItemOperationSerde> for expenser::LineItemOperation {
+    fn from(value: LineItemOperationSerde) -> Self {
+        use diesel::data_types::Cents;
+        let cents = |price_usd: f64| Cents((price_usd * 100.0).round() as i64);
+
+        match value {
+            LineItemOperationSerde::Create {
+                item_name,
+                item_price_usd,
+            } => Self::Create {
+                item_name,
+                item_price_usd: cents(item_price_usd),
+            },
+            LineItemOperationSerde::Update {
+                id,
+                item_name,
+                item_price_usd,
+                version,
+            } => Self::Update {
+                id,
+                item_name,
+                item_price_usd: cents(item_price_usd),
+                expected_version: version,
+            },
+            LineItemOperationSerde::Delete { id, version } => Self::Delete {
+                id,
+                expected_version: version,
+            },
+        }
+    }
+}
+
+impl FromVersioned for expenser::LineItemOperation {
+    type V1 = LineItemOperationSerde;
+
+    fn from_v1(body: Self::V1) -> Self {
+        body.into()
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct MoveLineItems {
+    pub to_report_id: i64,
+    pub item_ids: Vec<i64>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkStatus {
+    Applied,
+    /// Applied, then undone because a later operation failed
+    RolledBack,
+    Failed,
+    /// Not attempted because an earlier operation failed
+    Skipped,
+}
+
+/// Outcome of one operation of a bulk request, in request order
+#[derive(Serialize, Debug, ToSchema)]
+pub struct BulkResultSerde {
+    index: usize,
+    status: BulkStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    item: Option<ReportLineItemSerde>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Outcome of one operation of a bulk request, sent as [`BulkResultSerde`]
+pub struct BulkResult {
+    index: usize,
+    status: BulkStatus,
+    item: Option<expenser::ReportLineItem>,
+    error: Option<String>,
+}
+
+impl IntoVersioned for BulkResult {
+    type V1 = BulkResultSerde;
+
+    fn into_v1(self) -> Self::V1 {
+        BulkResultSerde {
+            index: self.index,
+            status: self.status,
+            item: self.item.map(Into::into),
+            error: self.error,
+        }
+    }
+}
+
+impl BulkResult {
+    pub fn applied(items: Vec<expenser::ReportLineItem>) -> Vec<Self> {
+        items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| Self {
+                index,
+                status: BulkStatus::Applied,
+                item: Some(item),
+                error: None,
+            })
+            .collect()
+    }
+
+    pub fn failed(count: usize, failed_index: usize, error: String) -> Vec<Self> {
+        (0..count)
+            .map(|index| {
+                let status = match index.cmp(&failed_index) {
+                    std::cmp::Ordering::Less => BulkStatus::RolledBack,
+                    std::cmp::Ordering::Equal => BulkStatus::Failed,
+                    std::cmp::Ordering::Greater => BulkStatus::Skipped,
+                };
+                Self {
+                    index,
+                    status,
+                    item: None,
+                    error: (index == failed_index).then(|| error.clone()),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct CommentBody {
+    pub line_item_id: Option<i64>,
+    pub body: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NotificationPreferencesSerde {
+    email_enabled: bool,
+    report_shared: bool,
+    report_submitted: bool,
+    report_approved: bool,
+    report_rejected: bool,
+    comment_added: bool,
+}
+
+impl NotificationPreferencesSerde {
+    pub fn for_user(self, user_id: i64) -> expenser::NotificationPreferences {
+        expenser::NotificationPreferences {
+            user_id,
+            email_enabled: self.email_enabled,
+            report_shared: self.report_shared,
+            report_submitted: self.report_submitted,
+            report_approved: self.report_approved,
+            report_rejected: self.report_rejected,
+            comment_added: self.comment_added,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NotificationQuery {
+    /// Include notifications which have already been read
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct UnreadCount {
+    pub unread: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+/// Runtime log levels, each one of `off`, `error`, `warn`, `info`, `debug` or `trace`
+pub struct LogLevelsSerde {
+    pub default: String,
+    /// Keyed by module path such as `server::webhooks`
+    #[serde(default)]
+    pub modules: std::collections::BTreeMap<String, String>,
+}
@@ -1,4 +1,10 @@
-use crate::AppState;
+use super::permissions::require_self;
+use super::types::NotificationPreferencesSerde;
+use crate::openapi::{
+    DatabaseError, DatabaseUnavailable, NotSelf, PatchRejected, TooManyRequests, Unauthenticated,
+    UploadTooLarge,
+};
+use crate::{Actor, AppState};
 use axum::{
     body::Bytes,
     extract::{Path, State},
@@ -6,16 +12,31 @@ use axum::{
     response::Result,
     Json,
 };
-use expenser::{NewUser, Report, ReportAccess, User, UserInfo};
+use expenser::{
+    InvalidPatch, NewUser, NotificationPreferences, Report, ReportAccess, User, UserInfo,
+};
+use serde_json::Value;
 
+/// Create a user
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "users",
+    request_body = NewUser,
+    responses(
+        (status = 200, description = "User created", body = UserInfo),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn create_user(
     State(state): State<AppState>,
+    Actor(actor): Actor,
     Json(payload): Json<NewUser>,
 ) -> Result<Json<UserInfo>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match payload.insert(database_connection) {
+    let res = match state.run(move |conn| payload.insert(actor, conn)).await? {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -26,14 +47,27 @@ pub async fn create_user(
     Ok(Json(res))
 }
 
+/// List the reports a user owns
+#[utoipa::path(
+    get,
+    path = "/users/{id}/reports",
+    tag = "users",
+    params(("id" = i64, Path, description = "Id of the user")),
+    responses(
+        (status = 200, description = "Reports owned by the user", body = [Report]),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
 #[axum::debug_handler]
 pub async fn get_reports_by_owner(
     Path(path): Path<i64>,
     State(state): State<AppState>,
 ) -> Result<Json<Vec<Report>>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match Report::get_by_owner(path, database_connection) {
+    let res = match state
+        .run(move |conn| Report::get_by_owner(path, conn))
+        .await?
+    {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -44,14 +78,27 @@ pub async fn get_reports_by_owner(
     Ok(Json(res))
 }
 
+/// List the reports a user has been granted access to
+#[utoipa::path(
+    get,
+    path = "/users/{id}/reports/access",
+    tag = "users",
+    params(("id" = i64, Path, description = "Id of the user")),
+    responses(
+        (status = 200, description = "Reports shared with the user", body = [Report]),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
 #[axum::debug_handler]
 pub async fn get_reports_by_view_access(
     Path(path): Path<i64>,
     State(state): State<AppState>,
 ) -> Result<Json<Vec<Report>>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match ReportAccess::get_report_by_borrower(path, database_connection) {
+    let res = match state
+        .run(move |conn| ReportAccess::get_report_by_borrower(path, conn))
+        .await?
+    {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -62,14 +109,26 @@ pub async fn get_reports_by_view_access(
     Ok(Json(res))
 }
 
+/// Get a user
+///
+/// The password hash and profile picture are not included.
+#[utoipa::path(
+    get,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i64, Path, description = "Id of the user")),
+    responses(
+        (status = 200, description = "The user", body = UserInfo),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
 #[axum::debug_handler]
 pub async fn get_user(
     Path(path): Path<i64>,
     State(state): State<AppState>,
 ) -> Result<Json<UserInfo>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match User::get_by_id(path, database_connection) {
+    let res = match state.run(move |conn| User::get_by_id(path, conn)).await? {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -80,16 +139,75 @@ pub async fn get_user(
     Ok(Json(res))
 }
 
+/// Replace a user
+#[utoipa::path(
+    put,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i64, Path, description = "Id of the user")),
+    request_body = NewUser,
+    responses(
+        (status = 200, description = "User updated", body = UserInfo),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn update_user(
     Path(path): Path<i64>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
     Json(payload): Json<NewUser>,
 ) -> Result<Json<UserInfo>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
+    let res = match state
+        .run(move |conn| User::replace(path, &payload, actor, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
 
-    let res = match User::replace(path, &payload, database_connection) {
+/// Partially update a user
+///
+/// Applies a JSON Merge Patch, only `username` and `email` can be changed. Passwords and
+/// profile pictures have their own endpoints.
+#[utoipa::path(
+    patch,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i64, Path, description = "Id of the user")),
+    request_body = UserPatch,
+    responses(
+        (status = 200, description = "User updated", body = UserInfo),
+        (status = 422, response = PatchRejected),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn patch_user(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+    Json(payload): Json<Value>,
+) -> Result<Json<UserInfo>, StatusCode> {
+    let res = match state
+        .run(move |conn| User::patch(path, &payload, actor, conn))
+        .await?
+    {
         Ok(res) => res,
+        Err(e) if e.is::<InvalidPatch>() => {
+            log::info!("{e}");
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
         Err(e) => {
             log::error!("{e}");
             return Err(StatusCode::BAD_GATEWAY);
@@ -99,14 +217,29 @@ pub async fn update_user(
     Ok(Json(res))
 }
 
+/// Delete a user
+#[utoipa::path(
+    delete,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i64, Path, description = "Id of the user")),
+    responses(
+        (status = 200, description = "User deleted", body = UserInfo),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn delete_user(
     Path(path): Path<i64>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
 ) -> Result<Json<UserInfo>, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match User::delete(path, database_connection) {
+    let res = match state
+        .run(move |conn| User::delete(path, actor, conn))
+        .await?
+    {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -118,10 +251,11 @@ pub async fn delete_user(
 }
 
 #[axum::debug_handler]
-pub async fn clear_users(State(state): State<AppState>) -> Result<(), StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    if let Err(e) = User::clear(database_connection) {
+pub async fn clear_users(
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+) -> Result<(), StatusCode> {
+    if let Err(e) = state.run(move |conn| User::clear(actor, conn)).await? {
         log::error!("{e}");
         Err(StatusCode::BAD_GATEWAY)
     } else {
@@ -129,14 +263,27 @@ pub async fn clear_users(State(state): State<AppState>) -> Result<(), StatusCode
     }
 }
 
+/// Get a user's profile picture
+#[utoipa::path(
+    get,
+    path = "/users/{id}/pfp",
+    tag = "users",
+    params(("id" = i64, Path, description = "Id of the user")),
+    responses(
+        (status = 200, description = "Image data, empty when the user has none", body = [u8]),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
 #[axum::debug_handler]
 pub async fn get_profile_picture(
     Path(path): Path<i64>,
     State(state): State<AppState>,
 ) -> Result<Bytes, StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    let res = match User::get_profile_picture(path, database_connection) {
+    let res = match state
+        .run(move |conn| User::get_profile_picture(path, conn))
+        .await?
+    {
         Ok(res) => res,
         Err(e) => {
             log::error!("{e}");
@@ -147,15 +294,33 @@ pub async fn get_profile_picture(
     Ok(res)
 }
 
+/// Replace a user's profile picture
+#[utoipa::path(
+    put,
+    path = "/users/{id}/pfp",
+    tag = "users",
+    params(("id" = i64, Path, description = "Id of the user")),
+    request_body(content = [u8], description = "Image data"),
+    responses(
+        (status = 200, description = "Profile picture updated"),
+        (status = 413, response = UploadTooLarge),
+        (status = 429, response = TooManyRequests),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn update_profile_picture(
     Path(path): Path<i64>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
     payload: Bytes,
 ) -> Result<(), StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    if let Err(e) = User::update_profile_picture(path, &payload, database_connection) {
+    if let Err(e) = state
+        .run(move |conn| User::update_profile_picture(path, &payload, actor, conn))
+        .await?
+    {
         log::error!("{e}");
         Err(StatusCode::BAD_GATEWAY)
     } else {
@@ -163,23 +328,121 @@ pub async fn update_profile_picture(
     }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
 pub struct Password {
     password: String,
 }
 
+/// Change a user's password
+#[utoipa::path(
+    put,
+    path = "/users/{id}/password",
+    tag = "users",
+    params(("id" = i64, Path, description = "Id of the user")),
+    request_body = Password,
+    responses(
+        (status = 200, description = "Password changed"),
+        (status = 429, response = TooManyRequests),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
 #[axum::debug_handler]
 pub async fn update_password(
     Path(path): Path<i64>,
     State(state): State<AppState>,
+    Actor(actor): Actor,
     Json(payload): Json<Password>,
 ) -> Result<(), StatusCode> {
-    let database_connection = &mut state.get_conn()?;
-
-    if let Err(e) = User::update_password(path, payload.password, database_connection) {
+    if let Err(e) = state
+        .run(move |conn| User::update_password(path, payload.password, actor, conn))
+        .await?
+    {
         log::error!("{e}");
         Err(StatusCode::BAD_GATEWAY)
     } else {
         Ok(())
     }
 }
+
+/// Get which notification emails a user receives
+///
+/// Users who have not saved preferences receive every notification. Only the user
+/// themselves may read them.
+#[utoipa::path(
+    get,
+    path = "/users/{id}/notification-preferences",
+    tag = "notifications",
+    params(("id" = i64, Path, description = "Id of the user")),
+    responses(
+        (status = 200, description = "The user's preferences", body = NotificationPreferences),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotSelf),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_notification_preferences(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<NotificationPreferences>, StatusCode> {
+    require_self(actor, path)?;
+
+    let res = match state
+        .run(move |conn| NotificationPreferences::get_by_user(path, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Change which notification emails a user receives
+///
+/// Only the user themselves may change them.
+#[utoipa::path(
+    put,
+    path = "/users/{id}/notification-preferences",
+    tag = "notifications",
+    params(("id" = i64, Path, description = "Id of the user")),
+    request_body = NotificationPreferencesSerde,
+    responses(
+        (status = 200, description = "Preferences saved", body = NotificationPreferences),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotSelf),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn update_notification_preferences(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<NotificationPreferencesSerde>,
+) -> Result<Json<NotificationPreferences>, StatusCode> {
+    require_self(actor, path)?;
+
+    let res = match state
+        .run(move |conn| payload.for_user(path).upsert(conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
@@ -0,0 +1,200 @@
+use crate::openapi::{DatabaseError, DatabaseUnavailable, Unauthenticated};
+use crate::{Actor, AppState};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Result,
+    Json,
+};
+use expenser::{Report, ReportVersion, ReportVersionDiff, MANUAL_SNAPSHOT};
+
+/// Snapshot the current state of a report
+///
+/// Stores the report, its line items and proof metadata as the next version.
+#[utoipa::path(
+    post,
+    path = "/reports/{report_id}/versions",
+    tag = "versions",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "Version created", body = ReportVersion),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security((), ("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn create_version(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+) -> Result<Json<ReportVersion>, StatusCode> {
+    let res = match state
+        .run(move |conn| ReportVersion::snapshot(path, MANUAL_SNAPSHOT, actor, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// List the versions of a report
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/versions",
+    tag = "versions",
+    params(("report_id" = i64, Path, description = "Id of the report")),
+    responses(
+        (status = 200, description = "Versions of the report", body = [ReportVersion]),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
+#[axum::debug_handler]
+pub async fn get_versions_by_report(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ReportVersion>>, StatusCode> {
+    let res = match state
+        .run(move |conn| ReportVersion::get_by_report(path, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Get a version of a report
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/versions/{version}",
+    tag = "versions",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("version" = i32, Path, description = "Version of the report"),
+    ),
+    responses(
+        (status = 200, description = "The version", body = ReportVersion),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
+#[axum::debug_handler]
+pub async fn get_version(
+    Path(path): Path<(i64, i32)>,
+    State(state): State<AppState>,
+) -> Result<Json<ReportVersion>, StatusCode> {
+    let res = match state
+        .run(move |conn| ReportVersion::get_by_version(path.0, path.1, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Compare two versions of a report
+#[utoipa::path(
+    get,
+    path = "/reports/{report_id}/versions/{version}/diff/{to}",
+    tag = "versions",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("version" = i32, Path, description = "Version to compare from"),
+        ("to" = i32, Path, description = "Version to compare to"),
+    ),
+    responses(
+        (status = 200, description = "Changes between the versions", body = ReportVersionDiff),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    )
+)]
+#[axum::debug_handler]
+pub async fn diff_versions(
+    Path(path): Path<(i64, i32, i32)>,
+    State(state): State<AppState>,
+) -> Result<Json<ReportVersionDiff>, StatusCode> {
+    let res = match state
+        .run(move |conn| ReportVersion::diff(path.0, path.1, path.2, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Restore a report to an earlier version
+///
+/// Only the owner of the report may restore it.
+#[utoipa::path(
+    post,
+    path = "/reports/{report_id}/versions/{version}/restore",
+    tag = "versions",
+    params(
+        ("report_id" = i64, Path, description = "Id of the report"),
+        ("version" = i32, Path, description = "Version of the report"),
+    ),
+    responses(
+        (status = 200, description = "Report restored", body = Report),
+        (status = 401, response = Unauthenticated),
+        (status = 403, description = "The acting user does not own the report"),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn restore_version(
+    Path(path): Path<(i64, i32)>,
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+) -> Result<Json<Report>, StatusCode> {
+    let report = match state
+        .run(move |conn| Report::get_by_id(path.0, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+    match actor {
+        None => return Err(StatusCode::UNAUTHORIZED),
+        Some(actor_id) if actor_id != report.owner_id => return Err(StatusCode::FORBIDDEN),
+        Some(_) => {}
+    }
+
+    let res = match state
+        .run(move |conn| ReportVersion::restore(path.0, path.1, actor, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
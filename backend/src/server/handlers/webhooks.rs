@@ -0,0 +1,295 @@
+use super::permissions::require_admin;
+use crate::openapi::{
+    DatabaseError, DatabaseUnavailable, InvalidWebhook, NotAdmin, Unauthenticated,
+};
+use crate::{Actor, AppState};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Result,
+    Json,
+};
+use expenser::{NewWebhook, Webhook, WebhookDelivery};
+
+/// Subscribe a url to events
+///
+/// Every change recorded in a report's history is delivered as `entity.action`, such as
+/// `report.update`. Deliveries are POSTed as JSON with the event in `X-Expenser-Event`, the
+/// delivery id in `X-Expenser-Delivery` and `sha256=` followed by the hex encoded
+/// HMAC-SHA256 of the body keyed with the secret in `X-Expenser-Signature`. Failed
+/// deliveries are retried with exponential backoff.
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    request_body = NewWebhook,
+    responses(
+        (status = 200, description = "Webhook created", body = Webhook),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotAdmin),
+        (status = 422, response = InvalidWebhook),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<NewWebhook>,
+) -> Result<Json<Webhook>, StatusCode> {
+    let admin_id = require_admin(&state, actor).await?;
+
+    if !payload.is_valid() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let res = match state
+        .run(move |conn| payload.insert(Some(admin_id), conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// List webhook subscriptions
+///
+/// Secrets are never returned.
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Every webhook", body = [Webhook]),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotAdmin),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_webhooks(
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<Vec<Webhook>>, StatusCode> {
+    require_admin(&state, actor).await?;
+
+    let res = match state.run(Webhook::get_all).await? {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Get a webhook subscription
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = i64, Path, description = "Id of the webhook")),
+    responses(
+        (status = 200, description = "The webhook", body = Webhook),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotAdmin),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_webhook(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<Webhook>, StatusCode> {
+    require_admin(&state, actor).await?;
+
+    let res = match state
+        .run(move |conn| Webhook::get_by_id(path, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Replace a webhook subscription
+#[utoipa::path(
+    put,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = i64, Path, description = "Id of the webhook")),
+    request_body = NewWebhook,
+    responses(
+        (status = 200, description = "Webhook updated", body = Webhook),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotAdmin),
+        (status = 422, response = InvalidWebhook),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn update_webhook(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(payload): Json<NewWebhook>,
+) -> Result<Json<Webhook>, StatusCode> {
+    let admin_id = require_admin(&state, actor).await?;
+
+    if !payload.is_valid() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let res = match state
+        .run(move |conn| Webhook::update(path, &payload, Some(admin_id), conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Delete a webhook subscription
+///
+/// Pending deliveries are dropped.
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = i64, Path, description = "Id of the webhook")),
+    responses(
+        (status = 200, description = "Webhook deleted", body = Webhook),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotAdmin),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn delete_webhook(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<Webhook>, StatusCode> {
+    let admin_id = require_admin(&state, actor).await?;
+
+    let res = match state
+        .run(move |conn| Webhook::delete(path, Some(admin_id), conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// List deliveries of a webhook
+///
+/// Newest first, with the number of attempts, last response status and error.
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(("id" = i64, Path, description = "Id of the webhook")),
+    responses(
+        (status = 200, description = "Deliveries of the webhook", body = [WebhookDelivery]),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotAdmin),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn get_webhook_deliveries(
+    Path(path): Path<i64>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<Vec<WebhookDelivery>>, StatusCode> {
+    require_admin(&state, actor).await?;
+
+    let res = match state
+        .run(move |conn| WebhookDelivery::get_by_webhook(path, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
+
+/// Queue a delivery to be sent again
+///
+/// Resets the attempt count, whether or not the delivery already succeeded.
+#[utoipa::path(
+    post,
+    path = "/webhooks/{id}/deliveries/{delivery_id}/redeliver",
+    tag = "webhooks",
+    params(
+        ("id" = i64, Path, description = "Id of the webhook"),
+        ("delivery_id" = i64, Path, description = "Id of the delivery"),
+    ),
+    responses(
+        (status = 200, description = "Delivery queued", body = WebhookDelivery),
+        (status = 401, response = Unauthenticated),
+        (status = 403, response = NotAdmin),
+        (status = 502, response = DatabaseError),
+        (status = 504, response = DatabaseUnavailable),
+    ),
+    security(("user_id" = []))
+)]
+#[axum::debug_handler]
+pub async fn redeliver_webhook_delivery(
+    Path(path): Path<(i64, i64)>,
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<WebhookDelivery>, StatusCode> {
+    require_admin(&state, actor).await?;
+
+    let res = match state
+        .run(move |conn| WebhookDelivery::redeliver(path, conn))
+        .await?
+    {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("{e}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    Ok(Json(res))
+}
@@ -0,0 +1,196 @@
+use crate::{actor::ACTOR_HEADER, AppState};
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, request::Parts, HeaderName, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use expenser::{IdempotencyClaim, IdempotencyKey};
+use http_body::{LengthLimitError, Limited};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on responses replayed from an earlier request with the same key
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+const MAX_KEY_LENGTH: usize = 255;
+/// How long a request holds its key before a retry may take it over
+///
+/// Abandoned requests release their key when dropped, this covers the server
+/// stopping before that could happen.
+const CLAIM_TIMEOUT: chrono::Duration = chrono::Duration::seconds(60);
+/// Response headers stored and replayed along with the status and body
+const STORED_HEADERS: [HeaderName; 3] = [header::CONTENT_TYPE, header::ETAG, header::LOCATION];
+
+/// Make POST requests carrying an `Idempotency-Key` header safe to retry
+///
+/// Keys are scoped to the actor. The first successful response for a key is stored, and
+/// later requests with the same key, method, path and body get it back without being
+/// handled again. Reusing a key for a different request is rejected with 422, and
+/// retrying while the first request is still being handled with 409. Failed requests
+/// release their key, as do requests dropped because the client disconnected.
+///
+/// The body is read before any extractor runs, so `DefaultBodyLimit` does not apply to
+/// it yet. It is capped at `max_body_bytes` here instead, answering 413 beyond that.
+pub async fn idempotency(
+    State((state, max_body_bytes)): State<(AppState, usize)>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let key = match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        None => return next.run(request).await,
+        Some(key) => match key.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_owned(),
+            _ => return StatusCode::BAD_REQUEST.into_response(),
+        },
+    };
+
+    let (parts, body) = request.into_parts();
+    let body = match hyper::body::to_bytes(Limited::new(body, max_body_bytes)).await {
+        Ok(body) => body,
+        Err(e) if e.is::<LengthLimitError>() => {
+            return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+        }
+        Err(e) => {
+            log::warn!("Unable to read request body: {e}");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    let actor_id = parts
+        .headers
+        .get(ACTOR_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok());
+
+    let claim = {
+        let digest = request_digest(&parts, &body);
+        let window = state.idempotency_window();
+        match state
+            .run(move |conn| {
+                IdempotencyKey::claim(&key, actor_id, &digest, window, CLAIM_TIMEOUT, conn)
+            })
+            .await
+        {
+            Ok(claim) => claim,
+            Err(status) => return status.into_response(),
+        }
+    };
+    let mut claim = match claim {
+        Ok(IdempotencyClaim::New(claimed)) => Claim {
+            state: state.clone(),
+            key: Some(claimed),
+        },
+        Ok(IdempotencyClaim::Replay(stored)) => return replay(stored),
+        Ok(IdempotencyClaim::InProgress) => return StatusCode::CONFLICT.into_response(),
+        Ok(IdempotencyClaim::Mismatch) => return StatusCode::UNPROCESSABLE_ENTITY.into_response(),
+        Err(e) => {
+            log::error!("{e}");
+            return StatusCode::BAD_GATEWAY.into_response();
+        }
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let (parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            log::error!("Unable to read response body: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if parts.status.is_success() {
+        let headers: Map<String, Value> = STORED_HEADERS
+            .iter()
+            .filter_map(|name| {
+                let value = parts.headers.get(name)?.to_str().ok()?;
+                Some((name.to_string(), Value::from(value)))
+            })
+            .collect();
+        let status = parts.status.as_u16().into();
+        let stored_body = body.clone();
+        let claimed = claim.key.clone().expect("claim is held until stored");
+        let res = state
+            .run(move |conn| claimed.complete(status, Value::Object(headers), &stored_body, conn))
+            .await
+            .map_err(anyhow::Error::msg)
+            .and_then(|res| res);
+        match res {
+            Ok(()) => claim.key = None,
+            Err(e) => log::error!("Unable to store response for idempotency key: {e}"),
+        }
+    }
+
+    Response::from_parts(parts, axum::body::boxed(axum::body::Full::from(body)))
+}
+
+/// Digest identifying a request, so a key cannot be reused for a different one
+fn request_digest(parts: &Parts, body: &Bytes) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(parts.method.as_str());
+    hasher.update([0]);
+    hasher.update(parts.uri.to_string());
+    hasher.update([0]);
+    if let Some(actor) = parts.headers.get(ACTOR_HEADER) {
+        hasher.update(actor.as_bytes());
+    }
+    hasher.update([0]);
+    hasher.update(body);
+
+    hex::encode(hasher.finalize())
+}
+
+fn replay(stored: IdempotencyKey) -> Response {
+    let status = stored
+        .response_status
+        .and_then(|status| StatusCode::from_u16(status as u16).ok())
+        .unwrap_or(StatusCode::OK);
+    let mut res = (status, stored.response_body.unwrap_or_default()).into_response();
+
+    if let Some(Value::Object(headers)) = stored.response_headers {
+        for (name, value) in headers {
+            if let (Ok(name), Some(Ok(value))) = (
+                HeaderName::try_from(name),
+                value.as_str().map(HeaderValue::from_str),
+            ) {
+                res.headers_mut().insert(name, value);
+            }
+        }
+    }
+    res.headers_mut()
+        .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+
+    res
+}
+
+/// Key claimed by a request, released when dropped unless its response was stored
+///
+/// Releasing on drop also covers requests whose future is dropped because the client
+/// disconnected, which would otherwise hold their key until the claim lapses.
+struct Claim {
+    state: AppState,
+    key: Option<IdempotencyKey>,
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let res = state
+                .run(move |conn| key.release(conn))
+                .await
+                .map_err(anyhow::Error::msg)
+                .and_then(|res| res);
+            if let Err(e) = res {
+                log::error!("Unable to release idempotency key: {e}");
+            }
+        });
+    }
+}
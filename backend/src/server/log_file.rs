@@ -0,0 +1,127 @@
+use crate::config::{LogConfig, LogRotation};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const CURRENT_FILE: &str = "expenser.log";
+const ROTATED_PREFIX: &str = "expenser.";
+const EXTENSION: &str = ".log";
+
+/// Log file that moves aside when it gets too large or a new hour or day starts
+///
+/// Lines always go to `expenser.log`; rotated files are named after the time they were
+/// started, e.g. `expenser.2026-10-19_14-00-00.log`, and only the newest are kept.
+pub struct RotatingFile {
+    dir: PathBuf,
+    rotation: LogRotation,
+    max_bytes: Option<u64>,
+    max_files: usize,
+    file: File,
+    size: u64,
+    started: DateTime<Local>,
+}
+
+impl RotatingFile {
+    pub fn open(dir: PathBuf, config: &LogConfig) -> Result<Self> {
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Unable to create log directory {}", dir.display()))?;
+        let path = dir.join(CURRENT_FILE);
+        let file = open(&path).with_context(|| format!("Unable to open {}", path.display()))?;
+        let metadata = file.metadata()?;
+
+        // Carry on with a file left by a previous run, rotating it if its period is over
+        let started = match metadata.created().or_else(|_| metadata.modified()) {
+            Ok(time) if metadata.len() > 0 => time.into(),
+            _ => Local::now(),
+        };
+
+        Ok(Self {
+            dir,
+            rotation: config.rotation,
+            max_bytes: config.max_file_bytes,
+            max_files: config.max_files,
+            file,
+            size: metadata.len(),
+            started,
+        })
+    }
+
+    /// Append a whole line, rotating first if it is due so lines are never split
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let now = Local::now();
+        let full = self
+            .max_bytes
+            .is_some_and(|max| self.size > 0 && self.size + line.len() as u64 > max);
+        if full || self.period(self.started) != self.period(now) {
+            self.rotate(now)?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn period(&self, time: DateTime<Local>) -> Option<String> {
+        match self.rotation {
+            LogRotation::Never => None,
+            LogRotation::Hourly => Some(time.format("%Y-%m-%d %H").to_string()),
+            LogRotation::Daily => Some(time.format("%Y-%m-%d").to_string()),
+        }
+    }
+
+    fn rotate(&mut self, now: DateTime<Local>) -> io::Result<()> {
+        if self.size > 0 {
+            let stamp = self.started.format("%Y-%m-%d_%H-%M-%S");
+            let mut rotated = self.dir.join(format!("{ROTATED_PREFIX}{stamp}{EXTENSION}"));
+            // Several size rotations can happen within a second
+            let mut n = 1;
+            while rotated.exists() {
+                rotated = self
+                    .dir
+                    .join(format!("{ROTATED_PREFIX}{stamp}_{n}{EXTENSION}"));
+                n += 1;
+            }
+
+            let current = self.dir.join(CURRENT_FILE);
+            fs::rename(&current, rotated)?;
+            self.file = open(&current)?;
+            self.size = 0;
+            self.prune()?;
+        }
+        self.started = now;
+
+        Ok(())
+    }
+
+    /// Delete the oldest rotated files beyond `max_files`
+    fn prune(&self) -> io::Result<()> {
+        let mut rotated = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if name != CURRENT_FILE && name.starts_with(ROTATED_PREFIX) && name.ends_with(EXTENSION)
+            {
+                rotated.push(name.to_owned());
+            }
+        }
+        // Timestamps in the names sort chronologically
+        rotated.sort();
+
+        let excess = rotated.len().saturating_sub(self.max_files);
+        for name in &rotated[..excess] {
+            fs::remove_file(self.dir.join(name))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
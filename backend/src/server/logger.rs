@@ -1,25 +1,114 @@
+use crate::config::{LogConfig, LogFormat};
+use crate::log_file::RotatingFile;
+use crate::request_id;
 use anyhow::{Context, Result};
+use log::LevelFilter;
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, RwLock},
+};
+
+/// Levels every record is checked against, changeable while the server runs
+#[derive(Clone)]
+pub struct Levels {
+    pub default: LevelFilter,
+    /// Keyed by module path, applying to that module and the modules below it
+    pub modules: BTreeMap<String, LevelFilter>,
+}
+
+static LEVELS: RwLock<Levels> = RwLock::new(Levels {
+    default: LevelFilter::Info,
+    modules: BTreeMap::new(),
+});
+
+impl Levels {
+    /// Level for a record target, taken from the most specific matching module
+    fn for_target(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                target
+                    .strip_prefix(module.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .values()
+            .copied()
+            .fold(self.default, |max, level| max.max(level))
+    }
+}
+
+/// Currently applied log levels
+pub fn levels() -> Levels {
+    LEVELS.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Replace the log levels, taking effect for the next record
+pub fn set_levels(levels: Levels) {
+    let max = levels.max();
+    *LEVELS.write().unwrap_or_else(|e| e.into_inner()) = levels;
+    log::set_max_level(max);
+}
 
 /// Logger configuration using [`fern`]
-pub fn setup() -> Result<()> {
-    let base_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or("/".to_owned());
-    let current_datetime = chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]");
-    let file_name = format!("{base_dir}/logs/{current_datetime}.log");
-
-    fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{} {:<30}  |  {}",
-                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
-                format!("[{}][{}]", record.target(), record.level()),
-                message
-            ))
+pub fn setup(config: &LogConfig) -> Result<()> {
+    let format = config.format;
+    let mut dispatch = fern::Dispatch::new()
+        .format(move |out, message, record| {
+            let now = chrono::Local::now();
+            let request_id = request_id::current();
+            match format {
+                LogFormat::Text => out.finish(format_args!(
+                    "{} {:<30}  |  {}",
+                    now.format("[%Y-%m-%d][%H:%M:%S]"),
+                    match request_id {
+                        Some(id) => format!("[{}][{}][{id}]", record.target(), record.level()),
+                        None => format!("[{}][{}]", record.target(), record.level()),
+                    },
+                    message
+                )),
+                LogFormat::Json => out.finish(format_args!(
+                    "{}",
+                    serde_json::json!({
+                        "timestamp": now.to_rfc3339(),
+                        "level": record.level().as_str(),
+                        "target": record.target(),
+                        "message": message.to_string(),
+                        "request_id": request_id,
+                    })
+                )),
+            }
         })
-        .level(log::LevelFilter::Info)
-        .chain(std::io::stdout())
-        .chain(fern::log_file(file_name).context("Unable to open log file")?)
-        .apply()
-        .context("Failed to dispatch logger")?;
+        // Filtering happens against the runtime levels instead
+        .level(LevelFilter::Trace)
+        .filter(|metadata| {
+            let levels = LEVELS.read().unwrap_or_else(|e| e.into_inner());
+            metadata.level() <= levels.for_target(metadata.target())
+        });
+
+    if config.stdout {
+        dispatch = dispatch.chain(std::io::stdout());
+    }
+    if let Some(dir) = &config.directory {
+        let file = Mutex::new(RotatingFile::open(dir.clone(), config)?);
+        dispatch = dispatch.chain(fern::Output::call(move |record| {
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            if let Err(e) = file.write_line(&format!("{}\n", record.args())) {
+                eprintln!("Unable to write log file: {e}");
+            }
+        }));
+    }
+
+    dispatch.apply().context("Failed to dispatch logger")?;
+    set_levels(Levels {
+        default: config.level()?,
+        modules: config.modules()?,
+    });
     log::info!("Configured fern logger");
 
     Ok(())
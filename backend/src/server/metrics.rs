@@ -0,0 +1,200 @@
+use crate::AppState;
+use anyhow::Result;
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use expenser::{OutboxMessage, Report, User, WebhookDelivery};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Prometheus metrics for the server, exposed at `/metrics` on `metrics.bind`
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    pool_connections: IntGauge,
+    pool_idle_connections: IntGauge,
+    pool_max_connections: IntGauge,
+    reports: IntGauge,
+    users: IntGauge,
+    pending_notifications: IntGauge,
+    pending_webhook_deliveries: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("expenser".to_owned()), None)?;
+
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "Requests handled, by route and status",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle requests, by route",
+            ),
+            &["method", "route"],
+        )?;
+        let pool_connections = IntGauge::new(
+            "db_pool_connections",
+            "Connections currently held by the pool, idle or checked out",
+        )?;
+        let pool_idle_connections =
+            IntGauge::new("db_pool_idle_connections", "Idle connections in the pool")?;
+        let pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Maximum number of connections the pool will open",
+        )?;
+        let reports = IntGauge::new("reports", "Expense reports")?;
+        let users = IntGauge::new("users", "Registered users")?;
+        let pending_notifications = IntGauge::new(
+            "pending_notifications",
+            "Notification emails waiting to be sent",
+        )?;
+        let pending_webhook_deliveries = IntGauge::new(
+            "pending_webhook_deliveries",
+            "Webhook deliveries waiting to be sent",
+        )?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(pool_connections.clone()))?;
+        registry.register(Box::new(pool_idle_connections.clone()))?;
+        registry.register(Box::new(pool_max_connections.clone()))?;
+        registry.register(Box::new(reports.clone()))?;
+        registry.register(Box::new(users.clone()))?;
+        registry.register(Box::new(pending_notifications.clone()))?;
+        registry.register(Box::new(pending_webhook_deliveries.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            request_duration,
+            pool_connections,
+            pool_idle_connections,
+            pool_max_connections,
+            reports,
+            users,
+            pending_notifications,
+            pending_webhook_deliveries,
+        })
+    }
+}
+
+/// Count requests and time them, labelled with the route template rather than the
+/// requested path so ids do not create new series
+pub async fn track<B>(
+    State(state): State<AppState>,
+    route: MatchedPath,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = request.method().clone();
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    let metrics = state.metrics();
+    metrics
+        .request_duration
+        .with_label_values(&[method.as_str(), route.as_str()])
+        .observe(start.elapsed().as_secs_f64());
+    metrics
+        .requests
+        .with_label_values(&[method.as_str(), route.as_str(), response.status().as_str()])
+        .inc();
+
+    response
+}
+
+/// Serve `/metrics` on its own address, apart from the API
+pub async fn serve(addr: SocketAddr, state: AppState) {
+    let app = Router::new().route("/metrics", get(render).with_state(state));
+
+    log::info!("Serving metrics on http://{addr}/metrics");
+    if let Err(e) = axum_server::bind(addr).serve(app.into_make_service()).await {
+        log::error!("Metrics server failed: {e}");
+    }
+}
+
+/// Background worker counting reports, users and queued deliveries every `interval`
+///
+/// Scrapes only read the gauges, so they never reach the database. If it cannot be
+/// reached the gauges keep their previous values.
+pub async fn refresh(state: AppState, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = state.shutdown().triggered() => break,
+        }
+        let counts = state
+            .run(|conn| -> Result<[i64; 4]> {
+                Ok([
+                    Report::count(conn)?,
+                    User::count(conn)?,
+                    OutboxMessage::count_pending(conn)?,
+                    WebhookDelivery::count_pending(conn)?,
+                ])
+            })
+            .await;
+        let metrics = state.metrics();
+        match counts {
+            Ok(Ok([reports, users, pending_notifications, pending_webhook_deliveries])) => {
+                metrics.reports.set(reports);
+                metrics.users.set(users);
+                metrics.pending_notifications.set(pending_notifications);
+                metrics
+                    .pending_webhook_deliveries
+                    .set(pending_webhook_deliveries);
+            }
+            Ok(Err(e)) => log::error!("Unable to refresh business metrics: {e}"),
+            Err(_) => log::error!("Unable to refresh business metrics, no database connection"),
+        }
+    }
+    log::info!("Stopped metrics worker");
+}
+
+/// Metrics handler, in the Prometheus text format
+///
+/// Pool gauges are read on every scrape, business gauges are kept up to date by [`refresh`].
+async fn render(State(state): State<AppState>) -> Result<Response, StatusCode> {
+    let metrics = state.metrics();
+
+    let pool = state.pool().state();
+    metrics.pool_connections.set(pool.connections.into());
+    metrics
+        .pool_idle_connections
+        .set(pool.idle_connections.into());
+    metrics
+        .pool_max_connections
+        .set(state.pool().max_size().into());
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&metrics.registry.gather(), &mut body) {
+        log::error!("{e}");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    Ok((
+        [(header::CONTENT_TYPE, encoder.format_type().to_owned())],
+        body,
+    )
+        .into_response())
+}
@@ -0,0 +1,108 @@
+use crate::config::{SmtpConfig, SmtpTls};
+use crate::AppState;
+use anyhow::{Context, Result};
+use expenser::OutboxMessage;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 20;
+/// How long a claimed message is hidden from other workers while it is being sent
+const LEASE_SECONDS: f64 = 300.0;
+
+/// SMTP transport for queued notification emails
+#[derive(Clone)]
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    /// Configure the transport, or `None` when `smtp.host` is unset
+    pub fn new(config: &SmtpConfig) -> Result<Option<Self>> {
+        let Some(host) = &config.host else {
+            return Ok(None);
+        };
+
+        let mut builder = match config.tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        let from = config
+            .from
+            .parse()
+            .context("smtp.from is not a valid mailbox")?;
+        log::info!("Configured SMTP transport for {host}");
+
+        Ok(Some(Self {
+            transport: builder.build(),
+            from,
+        }))
+    }
+
+    pub async fn send(&self, message: &OutboxMessage) -> Result<()> {
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(message.recipient.parse()?)
+            .subject(&message.subject)
+            .body(message.body.clone())?;
+        self.transport.send(email).await?;
+
+        Ok(())
+    }
+}
+
+/// Background worker delivering messages from the notification outbox
+pub async fn run(state: AppState, mailer: Mailer) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = state.shutdown().triggered() => break,
+        }
+        if let Err(e) = deliver_pending(&state, &mailer).await {
+            log::error!("{e}");
+        }
+    }
+    log::info!("Stopped notification worker");
+}
+
+async fn deliver_pending(state: &AppState, mailer: &Mailer) -> Result<()> {
+    let pending = expenser::database::run(state.pool(), |conn| {
+        OutboxMessage::claim_pending(BATCH_SIZE, LEASE_SECONDS, conn)
+    })
+    .await??;
+
+    for message in pending {
+        let id = message.id;
+
+        match mailer.send(&message).await {
+            Ok(()) => {
+                expenser::database::run(state.pool(), move |conn| message.mark_sent(conn))
+                    .await??;
+                log::info!("Delivered notification {id}");
+            }
+            Err(e) => {
+                log::warn!("Failed to deliver notification {id}: {e}");
+                expenser::database::run(state.pool(), move |conn| {
+                    message.mark_failed(&e.to_string(), conn)
+                })
+                .await??;
+            }
+        }
+    }
+
+    Ok(())
+}
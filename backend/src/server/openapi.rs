@@ -0,0 +1,463 @@
+use crate::events::ReportEvent;
+use crate::handlers::{self, types::*, Password};
+use axum::{
+    http::{header, HeaderValue},
+    Router,
+};
+use expenser::{
+    AuditLog, LineItemChange, LineItemSnapshot, NewReport, NewReportAccess, NewReportProof,
+    NewUser, NewWebhook, Notification, NotificationPreferences, ProofSnapshot, Report,
+    ReportAccess, ReportAccessPatch, ReportComment, ReportLineItemPatch, ReportPatch, ReportProof,
+    ReportVersion, ReportVersionDiff, UserInfo, UserPatch, Webhook, WebhookDelivery,
+};
+use tower_http::set_header::SetResponseHeaderLayer;
+use utoipa::{
+    openapi::{
+        path::{ParameterBuilder, ParameterIn, PathItemType},
+        security::{ApiKey, ApiKeyValue, SecurityScheme},
+        ObjectBuilder, Ref, RefOr, Required, SchemaType,
+    },
+    Modify, OpenApi, ToResponse,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+/// The docs page loads its scripts and styles from the server, and inlines some styles
+const DOCS_CSP: &str =
+    "default-src 'self'; img-src 'self' data:; style-src 'self' 'unsafe-inline'; frame-ancestors 'none'";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Expenser Backend",
+        version = "1.0.1",
+        description = "A backend that handles the management of expense reports and user identities. Every response carries an `X-Request-Id` header, echoing the request's own when it is at most 128 visible ASCII characters and otherwise newly generated, which is also included in the server's log lines for that request. Requests are rate limited per client address, answering 429 with a `Retry-After` header; uploads and password changes have stricter limits. Responses carry security headers (`X-Content-Type-Options`, `Referrer-Policy`, `Content-Security-Policy`, `X-Frame-Options`, and `Strict-Transport-Security` over TLS). State-changing requests that carry cookies are refused with 403 unless they come from the API's own origin or an allowed CORS origin. Routes are served under `/api/v1`. The unversioned `/api` routes behave as v1 for clients from before versioning and are deprecated: their responses carry a `Deprecation` header, a `Sunset` header once a date is set, and a `Link` to the same route under the latest version, and after the sunset date they answer 410. Bodies that cannot be parsed are refused with 400, 415 or 422, and a malformed `X-User-Id` header with 400, before reaching any operation.",
+        contact(email = "grantlemons@aol.com"),
+        license(name = "GPLv3", url = "https://www.gnu.org/licenses/gpl-3.0.en.html"),
+    ),
+    servers(
+        (url = "/api/v1", description = "Version 1"),
+        (url = "/api", description = "Routes from before versioning, deprecated"),
+    ),
+    tags(
+        (name = "reports"),
+        (name = "access"),
+        (name = "line_items"),
+        (name = "proof"),
+        (name = "comments"),
+        (name = "notifications"),
+        (name = "users"),
+        (name = "versions"),
+        (name = "webhooks"),
+        (name = "health"),
+        (name = "admin"),
+    ),
+    paths(
+        handlers::legacy_health,
+        handlers::health,
+        handlers::ready,
+        handlers::info,
+        handlers::create_report,
+        handlers::get_report,
+        handlers::update_report,
+        handlers::patch_report,
+        handlers::delete_report,
+        handlers::get_report_history,
+        handlers::get_report_events,
+        handlers::get_versions_by_report,
+        handlers::create_version,
+        handlers::get_version,
+        handlers::restore_version,
+        handlers::diff_versions,
+        handlers::get_line_items_by_report,
+        handlers::create_line_item,
+        handlers::clear_line_items,
+        handlers::bulk_line_items,
+        handlers::move_line_items,
+        handlers::get_line_item,
+        handlers::update_line_item,
+        handlers::patch_line_item,
+        handlers::delete_line_item,
+        handlers::get_access_by_report,
+        handlers::create_access,
+        handlers::clear_access,
+        handlers::get_access,
+        handlers::update_access,
+        handlers::patch_access,
+        handlers::delete_access,
+        handlers::get_proof_by_report,
+        handlers::create_proof,
+        handlers::clear_proof,
+        handlers::get_proof,
+        handlers::update_proof,
+        handlers::delete_proof,
+        handlers::get_comments_by_report,
+        handlers::create_comment,
+        handlers::get_comment,
+        handlers::update_comment,
+        handlers::delete_comment,
+        handlers::get_comments_by_line_item,
+        handlers::create_user,
+        handlers::get_user,
+        handlers::update_user,
+        handlers::patch_user,
+        handlers::delete_user,
+        handlers::get_profile_picture,
+        handlers::update_profile_picture,
+        handlers::update_password,
+        handlers::get_notifications,
+        handlers::get_unread_count,
+        handlers::mark_all_notifications_read,
+        handlers::mark_notification_read,
+        handlers::get_notification_preferences,
+        handlers::update_notification_preferences,
+        handlers::get_reports_by_owner,
+        handlers::get_reports_by_view_access,
+        handlers::get_webhooks,
+        handlers::create_webhook,
+        handlers::get_webhook,
+        handlers::update_webhook,
+        handlers::delete_webhook,
+        handlers::get_webhook_deliveries,
+        handlers::redeliver_webhook_delivery,
+        handlers::get_log_levels,
+        handlers::update_log_levels,
+    ),
+    components(
+        schemas(
+            CrateInfo,
+            Readiness,
+            ReadinessStatus,
+            ComponentStatus,
+            MigrationStatus,
+            NewUser,
+            UserInfo,
+            UserPatch,
+            Password,
+            Report,
+            NewReport,
+            ReportPatch,
+            AuditLog,
+            ReportEvent,
+            ReportVersion,
+            ReportVersionDiff,
+            LineItemSnapshot,
+            LineItemChange,
+            ProofSnapshot,
+            ReportLineItemSerde,
+            NewReportLineItemSerde,
+            ReportLineItemPatch,
+            LineItemOperationSerde,
+            MoveLineItems,
+            BulkStatus,
+            BulkResultSerde,
+            ReportAccess,
+            NewReportAccess,
+            ReportAccessPatch,
+            ReportProof,
+            NewReportProof,
+            ReportComment,
+            CommentBody,
+            Notification,
+            NotificationPreferences,
+            NotificationPreferencesSerde,
+            UnreadCount,
+            Webhook,
+            NewWebhook,
+            WebhookDelivery,
+            LogLevelsSerde,
+        ),
+        responses(
+            DatabaseError,
+            DatabaseUnavailable,
+            Unauthenticated,
+            Forbidden,
+            NotAdmin,
+            NotSelf,
+            PreconditionFailed,
+            PreconditionRequired,
+            PatchRejected,
+            InvalidWebhook,
+            UploadTooLarge,
+            TooManyRequests,
+            IdempotencyConflict,
+        ),
+    ),
+    modifiers(&UserIdHeader, &Idempotency),
+)]
+pub struct ApiDoc;
+
+/// Database error, or the requested resource does not exist
+#[derive(ToResponse)]
+pub struct DatabaseError;
+
+/// Unable to get a database connection in time
+#[derive(ToResponse)]
+pub struct DatabaseUnavailable;
+
+/// No `X-User-Id` header was sent
+#[derive(ToResponse)]
+pub struct Unauthenticated;
+
+/// The acting user does not have access to the report
+#[derive(ToResponse)]
+pub struct Forbidden;
+
+/// The acting user is not an administrator
+#[derive(ToResponse)]
+pub struct NotAdmin;
+
+/// The acting user is not the user in the path
+#[derive(ToResponse)]
+pub struct NotSelf;
+
+/// `If-Match` does not match the resource's current `ETag`
+#[derive(ToResponse)]
+pub struct PreconditionFailed;
+
+/// `If-Match` is required to change the resource
+#[derive(ToResponse)]
+pub struct PreconditionRequired;
+
+/// The patch tries to change a field that cannot be changed
+#[derive(ToResponse)]
+pub struct PatchRejected;
+
+/// The url is not http(s) or no events were given
+#[derive(ToResponse)]
+pub struct InvalidWebhook;
+
+/// The upload is larger than the configured limit
+#[derive(ToResponse)]
+pub struct UploadTooLarge;
+
+/// Rate limited
+#[derive(ToResponse)]
+#[response(headers(
+    ("Retry-After" = u64, description = "Seconds until the request will be accepted again")
+))]
+pub struct TooManyRequests;
+
+/// A request with the same `Idempotency-Key` is still being handled
+#[derive(ToResponse)]
+pub struct IdempotencyConflict;
+
+/// Describes the `X-User-Id` header identifying the acting user
+struct UserIdHeader;
+
+impl Modify for UserIdHeader {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let Some(components) = openapi.components.as_mut() else {
+            return;
+        };
+        components.add_security_scheme(
+            "user_id",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-User-Id",
+                "Id of the user making the request, until requests are authenticated",
+            ))),
+        );
+    }
+}
+
+/// Documents the `Idempotency-Key` header accepted by every POST
+struct Idempotency;
+
+impl Modify for Idempotency {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for item in openapi.paths.paths.values_mut() {
+            let Some(operation) = item.operations.get_mut(&PathItemType::Post) else {
+                continue;
+            };
+            let key = ParameterBuilder::new()
+                .name("Idempotency-Key")
+                .parameter_in(ParameterIn::Header)
+                .required(Required::False)
+                .description(Some(
+                    "Makes the request safe to retry, repeats get the first successful response back. Reusing a key for a different request is refused with 422.",
+                ))
+                .schema(Some(
+                    ObjectBuilder::new()
+                        .schema_type(SchemaType::String)
+                        .min_length(Some(1))
+                        .max_length(Some(255)),
+                ))
+                .build();
+            operation.parameters.get_or_insert_with(Vec::new).push(key);
+
+            operation.responses.responses.insert(
+                "409".to_owned(),
+                RefOr::Ref(Ref::from_response_name("IdempotencyConflict")),
+            );
+        }
+    }
+}
+
+/// Interactive documentation at `/api/docs`, and the document itself at `/api/openapi.json`
+pub fn docs() -> Router {
+    Router::from(SwaggerUi::new("/api/docs").url("/api/openapi.json", ApiDoc::openapi())).layer(
+        SetResponseHeaderLayer::overriding(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(DOCS_CSP),
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApiDoc;
+    use crate::{config::LimitsConfig, AppState};
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{header, Method, Request, StatusCode},
+    };
+    use hyper::service::Service;
+    use serde_json::Value;
+    use std::{collections::BTreeSet, net::SocketAddr};
+    use utoipa::OpenApi;
+
+    const SPEC: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/spec/schema.yaml");
+
+    /// Paths routed by `api`, in OpenAPI's `{param}` form
+    fn routed_paths() -> BTreeSet<String> {
+        let state = AppState::detached();
+        let routes = crate::routes(&state)
+            .into_iter()
+            .chain(crate::upload_routes(&state, &LimitsConfig::default()));
+
+        routes
+            .map(|(path, _)| {
+                path.split('/')
+                    .map(|segment| match segment.strip_prefix(':') {
+                        Some(param) => format!("{{{param}}}"),
+                        None => segment.to_owned(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn committed_spec_is_up_to_date() {
+        let generated = ApiDoc::openapi()
+            .to_yaml()
+            .expect("Unable to serialize spec");
+
+        if std::env::var_os("UPDATE_SPEC").is_some() {
+            std::fs::write(SPEC, generated).expect("Unable to write spec");
+            return;
+        }
+        let committed = std::fs::read_to_string(SPEC).expect("Unable to read spec");
+        assert!(
+            committed == generated,
+            "spec/schema.yaml differs from the handler annotations, regenerate it with `UPDATE_SPEC=1 cargo test`"
+        );
+    }
+
+    /// Every `$ref` in `value`
+    fn references(value: &Value) -> Vec<&str> {
+        match value {
+            Value::Object(map) => map
+                .iter()
+                .flat_map(|(key, value)| match (key.as_str(), value) {
+                    ("$ref", Value::String(reference)) => vec![reference.as_str()],
+                    _ => references(value),
+                })
+                .collect(),
+            Value::Array(values) => values.iter().flat_map(references).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn references_resolve() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        for reference in references(&spec) {
+            let pointer = reference.strip_prefix('#').unwrap_or(reference);
+            assert!(
+                spec.pointer(pointer).is_some(),
+                "{reference} does not exist, is the type listed in the components of ApiDoc?"
+            );
+        }
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let documented: BTreeSet<String> = ApiDoc::openapi().paths.paths.into_keys().collect();
+        let routed = routed_paths();
+
+        let undocumented: Vec<_> = routed.difference(&documented).collect();
+        let unrouted: Vec<_> = documented.difference(&routed).collect();
+        assert!(
+            undocumented.is_empty(),
+            "Routed but not documented: {undocumented:?}"
+        );
+        assert!(
+            unrouted.is_empty(),
+            "Documented but not routed: {unrouted:?}"
+        );
+    }
+
+    /// Each documented path answers exactly the documented methods
+    ///
+    /// Unsupported methods are answered with 405 and an `Allow` header listing the
+    /// routed ones, without reaching a handler or the database.
+    #[tokio::test]
+    async fn documented_methods_are_routed() {
+        let mut app = crate::api(AppState::detached(), &LimitsConfig::default());
+
+        for (path, item) in ApiDoc::openapi().paths.paths {
+            let uri = path
+                .split('/')
+                .map(|segment| {
+                    if segment.starts_with('{') {
+                        "1"
+                    } else {
+                        segment
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            let mut request = Request::builder()
+                .method(Method::TRACE)
+                .uri(&uri)
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))));
+
+            let response = app.call(request).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{path} is documented but not routed"
+            );
+
+            let allowed: BTreeSet<String> = response.headers()[header::ALLOW]
+                .to_str()
+                .unwrap()
+                .split(',')
+                .map(|method| method.trim().to_owned())
+                .filter(|method| method != "HEAD")
+                .collect();
+            let documented: BTreeSet<String> = item
+                .operations
+                .keys()
+                .map(|method| {
+                    serde_json::to_value(method)
+                        .unwrap()
+                        .as_str()
+                        .unwrap()
+                        .to_uppercase()
+                })
+                .collect();
+            assert_eq!(
+                allowed, documented,
+                "Methods of {path} differ from its spec"
+            );
+        }
+    }
+}
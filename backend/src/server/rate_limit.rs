@@ -0,0 +1,253 @@
+use crate::config::{Quota, RateLimitConfig};
+use crate::AppState;
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Full buckets are forgotten this often, so idle clients do not accumulate
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    map: HashMap<IpAddr, Bucket>,
+    pruned: Instant,
+}
+
+/// Token buckets keyed by client address, refilling at a steady rate up to a burst
+///
+/// Not keyed by `X-User-Id`, which is unverified and would let anyone drain another
+/// user's bucket.
+pub struct Limiter {
+    /// Tokens added per second
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<Buckets>,
+}
+
+impl Limiter {
+    pub fn new(quota: Quota) -> Self {
+        Self {
+            rate: f64::from(quota.per_minute) / 60.0,
+            burst: f64::from(quota.burst),
+            buckets: Mutex::new(Buckets {
+                map: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token from the client's bucket
+    ///
+    /// Returns how long until the bucket has a token again when the request is refused.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if now.duration_since(buckets.pruned) > PRUNE_INTERVAL {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.map.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst
+            });
+            buckets.pruned = now;
+        }
+
+        let bucket = buckets.map.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate));
+        }
+        bucket.tokens -= 1.0;
+
+        Ok(())
+    }
+}
+
+/// Limiters for each class of request
+pub struct RateLimits {
+    trust_forwarded_for: bool,
+    requests: Limiter,
+    uploads: Limiter,
+    auth: Limiter,
+}
+
+impl RateLimits {
+    /// `None` when rate limiting is disabled
+    pub fn new(config: &RateLimitConfig) -> Option<Self> {
+        config.enabled.then(|| Self {
+            trust_forwarded_for: config.trust_forwarded_for,
+            requests: Limiter::new(config.requests),
+            uploads: Limiter::new(config.uploads),
+            auth: Limiter::new(config.auth),
+        })
+    }
+
+    fn client(&self, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+        let forwarded = self
+            .trust_forwarded_for
+            .then(|| headers.get("x-forwarded-for"))
+            .flatten()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|addr| addr.trim().parse().ok());
+
+        forwarded.unwrap_or(peer.ip())
+    }
+}
+
+async fn limit<B>(
+    state: AppState,
+    select: fn(&RateLimits) -> &Limiter,
+    peer: SocketAddr,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(limits) = state.rate_limits() else {
+        return next.run(request).await;
+    };
+
+    let client = limits.client(peer, request.headers());
+    match select(limits).check(client) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => {
+            log::warn!(
+                "Rate limited {} {} for {client}",
+                request.method(),
+                request.uri().path()
+            );
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after(wait).to_string())],
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Whole seconds to wait, rounded up so a client retrying on time is not refused again
+fn retry_after(wait: Duration) -> u64 {
+    wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+}
+
+/// Limit every API request
+pub async fn requests<B>(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    limit(state, |limits| &limits.requests, peer, request, next).await
+}
+
+/// Limit uploads, reads of the same routes only count towards [`requests`]
+pub async fn uploads<B>(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if request.method().is_safe() {
+        return next.run(request).await;
+    }
+    limit(state, |limits| &limits.uploads, peer, request, next).await
+}
+
+/// Limit changes to credentials
+pub async fn auth<B>(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    limit(state, |limits| &limits.auth, peer, request, next).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_after, Limiter};
+    use crate::config::Quota;
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    fn client(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn burst_is_allowed_then_refused() {
+        let limiter = Limiter::new(Quota {
+            per_minute: 60,
+            burst: 3,
+        });
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(client(1), now), Ok(()));
+        }
+        assert_eq!(
+            limiter.check_at(client(1), now),
+            Err(Duration::from_secs(1))
+        );
+        assert_eq!(limiter.check_at(client(2), now), Ok(()));
+    }
+
+    #[test]
+    fn buckets_refill_over_time_up_to_the_burst() {
+        let limiter = Limiter::new(Quota {
+            per_minute: 60,
+            burst: 2,
+        });
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            assert_eq!(limiter.check_at(client(1), now), Ok(()));
+        }
+        let half = now + Duration::from_millis(500);
+        assert_eq!(
+            limiter.check_at(client(1), half),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(
+            limiter.check_at(client(1), now + Duration::from_secs(1)),
+            Ok(())
+        );
+
+        let later = now + Duration::from_secs(30);
+        for _ in 0..2 {
+            assert_eq!(limiter.check_at(client(1), later), Ok(()));
+        }
+        assert!(limiter.check_at(client(1), later).is_err());
+    }
+
+    #[test]
+    fn retry_after_rounds_up_to_whole_seconds() {
+        assert_eq!(retry_after(Duration::ZERO), 0);
+        assert_eq!(retry_after(Duration::from_nanos(1)), 1);
+        assert_eq!(retry_after(Duration::from_millis(999)), 1);
+        assert_eq!(retry_after(Duration::from_secs(1)), 1);
+        assert_eq!(retry_after(Duration::from_millis(1001)), 2);
+        assert_eq!(retry_after(Duration::from_secs(60)), 60);
+    }
+}
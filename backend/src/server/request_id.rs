@@ -0,0 +1,62 @@
+use axum::{
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::cell::RefCell;
+
+pub static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+/// Longer incoming ids are replaced rather than logged
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: String;
+}
+
+thread_local! {
+    /// Set while a request's diesel calls run on the blocking thread pool
+    static BLOCKING: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Request id of the request being handled on this task or thread, if any
+pub fn current() -> Option<String> {
+    CURRENT
+        .try_with(Clone::clone)
+        .ok()
+        .or_else(|| BLOCKING.with(|id| id.borrow().clone()))
+}
+
+/// Run `f` with `id` as the current request id, for work moved off the request's task
+pub fn scope_blocking<T>(id: Option<String>, f: impl FnOnce() -> T) -> T {
+    let previous = BLOCKING.with(|current| current.replace(id));
+    let res = f();
+    BLOCKING.with(|current| current.replace(previous));
+    res
+}
+
+/// Tag each request with an id, reusing the client's `X-Request-Id` when it is usable
+///
+/// The id is included in every log line written while handling the request and echoed
+/// back on the response.
+pub async fn propagate<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_owned)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    // Only visible ascii reaches here, so the header value is always valid
+    let value = HeaderValue::from_str(&id).expect("Request id is not a valid header");
+    request
+        .headers_mut()
+        .insert(REQUEST_ID.clone(), value.clone());
+
+    let mut response = CURRENT.scope(id, next.run(request)).await;
+    response.headers_mut().insert(REQUEST_ID.clone(), value);
+    response
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LENGTH && id.bytes().all(|b| b.is_ascii_graphic())
+}
@@ -0,0 +1,77 @@
+use crate::config::CorsConfig;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+/// Origins trusted to make state-changing requests with the browser's cookies
+pub struct Csrf {
+    allowed_origins: Vec<String>,
+}
+
+impl Csrf {
+    pub fn new(cors: &CorsConfig) -> Arc<Self> {
+        Arc::new(Self {
+            // `*` allows reading responses from anywhere, not sending cookies from anywhere
+            allowed_origins: cors
+                .allowed_origins
+                .iter()
+                .filter(|origin| *origin != "*")
+                .cloned()
+                .collect(),
+        })
+    }
+
+    fn allows(&self, headers: &HeaderMap) -> bool {
+        let Some(origin) = origin(headers) else {
+            return false;
+        };
+        if self.allowed_origins.contains(&origin) {
+            return true;
+        }
+
+        // Same origin, whichever scheme the API is served over
+        let host = headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok());
+        host.is_some() && host == origin.split_once("://").map(|(_, host)| host)
+    }
+}
+
+/// Origin of the page making a request, from `Origin` or failing that `Referer`
+fn origin(headers: &HeaderMap) -> Option<String> {
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        return origin.to_str().ok().map(str::to_owned);
+    }
+
+    let referer = headers.get(header::REFERER)?.to_str().ok()?;
+    let (scheme, rest) = referer.split_once("://")?;
+    let host = rest.split(['/', '?', '#']).next()?;
+    Some(format!("{scheme}://{host}"))
+}
+
+/// Refuse cross-site forgeries of state-changing requests
+///
+/// Requests authenticated by a header cannot be forged cross-site, so only requests
+/// carrying cookies are checked. Their origin must be the API's own or an allowed one.
+pub async fn protect<B>(
+    State(csrf): State<Arc<Csrf>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let headers = request.headers();
+    if request.method().is_safe() || !headers.contains_key(header::COOKIE) || csrf.allows(headers) {
+        return next.run(request).await;
+    }
+
+    log::warn!(
+        "Refused cross-site {} {} from {}",
+        request.method(),
+        request.uri().path(),
+        origin(headers).as_deref().unwrap_or("an unknown origin")
+    );
+    StatusCode::FORBIDDEN.into_response()
+}
@@ -1,113 +1,352 @@
 use anyhow::Result;
-use axum::Router;
+use axum::{extract::DefaultBodyLimit, routing::MethodRouter, Router};
+use config::{Config, LimitsConfig};
+use std::{net::SocketAddr, time::Duration};
+use tower_http::set_header::SetResponseHeaderLayer;
 
 #[allow(unused_imports)]
 use expenser::*;
 
 mod handlers {
     mod access;
+    mod admin;
+    mod comments;
+    mod events;
     /// Handlers for server info and health check
     mod info;
     mod line_items;
+    mod notifications;
+    mod permissions;
     mod proof;
     mod reports;
-    mod types;
+    pub(crate) mod types;
     mod users;
+    mod versions;
+    mod webhooks;
 
     pub(crate) use access::*;
+    pub(crate) use admin::*;
+    pub(crate) use comments::*;
+    pub(crate) use events::*;
     pub(crate) use info::*;
     pub(crate) use line_items::*;
+    pub(crate) use notifications::*;
     pub(crate) use proof::*;
     pub(crate) use reports::*;
     pub(crate) use users::*;
+    pub(crate) use versions::*;
+    pub(crate) use webhooks::*;
 }
+mod actor;
+mod api_version;
+mod config;
+mod etag;
+mod events;
+mod idempotency;
+mod log_file;
 mod logger;
+mod metrics;
+mod notifications;
+mod openapi;
+mod rate_limit;
+mod request_id;
+mod security;
+mod shutdown;
 mod state;
+mod telemetry;
+mod tls;
+mod webhooks;
+pub use actor::Actor;
+pub use api_version::{ApiVersion, VersionedJson};
+pub use etag::{ETag, IfMatch};
 pub use state::AppState;
 
-const PORT: u16 = 3000;
-const LOCALHOST: [u8; 4] = [0, 0, 0, 0];
+/// Path and handlers of each API route
+type Routes = Vec<(&'static str, MethodRouter<AppState>)>;
 
-fn api() -> Result<Router> {
-    #[allow(unused_imports)]
-    use axum::routing::{delete, get, post, put};
+/// Every route but uploads, shared by [`api`] and the OpenAPI tests
+fn routes(state: &AppState) -> Routes {
+    use axum::routing::{get, post, put};
     use handlers::*;
 
-    let router = Router::new()
-        .route("/health", get(health)) // Health check
-        .route("/info", get(info))
-        .route("/reports", post(create_report))
-        .route(
-            "/reports/:id",
-            get(get_report).put(update_report).delete(delete_report),
-        )
-        .route(
+    vec![
+        ("/health", get(legacy_health)),
+        ("/health/live", get(health)),
+        ("/health/ready", get(ready)),
+        ("/info", get(info)),
+        ("/reports", post(create_report)),
+        (
+            "/reports/:report_id",
+            get(get_report)
+                .put(update_report)
+                .patch(patch_report)
+                .delete(delete_report),
+        ),
+        ("/reports/:report_id/history", get(get_report_history)),
+        ("/reports/:report_id/events", get(get_report_events)),
+        (
+            "/reports/:report_id/versions",
+            get(get_versions_by_report).post(create_version),
+        ),
+        ("/reports/:report_id/versions/:version", get(get_version)),
+        (
+            "/reports/:report_id/versions/:version/restore",
+            post(restore_version),
+        ),
+        (
+            "/reports/:report_id/versions/:version/diff/:to",
+            get(diff_versions),
+        ),
+        (
             "/reports/:report_id/items",
             get(get_line_items_by_report)
                 .post(create_line_item)
                 .delete(clear_line_items),
-        )
-        .route(
+        ),
+        ("/reports/:report_id/items/bulk", post(bulk_line_items)),
+        ("/reports/:report_id/items/move", post(move_line_items)),
+        (
             "/reports/:report_id/items/:id",
             get(get_line_item)
                 .put(update_line_item)
+                .patch(patch_line_item)
                 .delete(delete_line_item),
-        )
-        .route(
+        ),
+        (
             "/reports/:report_id/access",
             get(get_access_by_report)
                 .post(create_access)
                 .delete(clear_access),
-        )
-        .route(
+        ),
+        (
             "/reports/:report_id/access/:id",
-            get(get_access).put(update_access).delete(delete_access),
-        )
-        .route(
-            "/reports/:report_id/proof",
-            get(get_proof_by_report)
-                .post(create_proof)
-                .delete(clear_proof),
-        )
-        .route(
+            get(get_access)
+                .put(update_access)
+                .patch(patch_access)
+                .delete(delete_access),
+        ),
+        (
             "/reports/:report_id/proof/:id",
             get(get_proof).put(update_proof).delete(delete_proof),
-        )
-        .route("/users", post(create_user))
-        .route(
+        ),
+        (
+            "/reports/:report_id/comments",
+            get(get_comments_by_report).post(create_comment),
+        ),
+        (
+            "/reports/:report_id/comments/:id",
+            get(get_comment).put(update_comment).delete(delete_comment),
+        ),
+        (
+            "/reports/:report_id/items/:id/comments",
+            get(get_comments_by_line_item),
+        ),
+        ("/users", post(create_user)),
+        (
             "/users/:id",
-            get(get_user).put(update_user).delete(delete_user),
-        )
-        .route(
+            get(get_user)
+                .put(update_user)
+                .patch(patch_user)
+                .delete(delete_user),
+        ),
+        (
+            "/users/:id/password",
+            put(update_password).layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                rate_limit::auth,
+            )),
+        ),
+        ("/users/:id/notifications", get(get_notifications)),
+        ("/users/:id/notifications/count", get(get_unread_count)),
+        (
+            "/users/:id/notifications/read",
+            post(mark_all_notifications_read),
+        ),
+        (
+            "/users/:id/notifications/:notification_id/read",
+            post(mark_notification_read),
+        ),
+        (
+            "/users/:id/notification-preferences",
+            get(get_notification_preferences).put(update_notification_preferences),
+        ),
+        ("/users/:id/reports", get(get_reports_by_owner)),
+        ("/users/:id/reports/access", get(get_reports_by_view_access)),
+        ("/webhooks", get(get_webhooks).post(create_webhook)),
+        (
+            "/webhooks/:id",
+            get(get_webhook).put(update_webhook).delete(delete_webhook),
+        ),
+        ("/webhooks/:id/deliveries", get(get_webhook_deliveries)),
+        (
+            "/webhooks/:id/deliveries/:delivery_id/redeliver",
+            post(redeliver_webhook_delivery),
+        ),
+        (
+            "/admin/log-levels",
+            get(get_log_levels).put(update_log_levels),
+        ),
+    ]
+}
+
+/// Upload routes, which buffer bodies for idempotency up to their own, larger limit
+fn upload_routes(state: &AppState, limits: &LimitsConfig) -> Routes {
+    use axum::routing::get;
+    use handlers::*;
+
+    vec![
+        (
+            "/reports/:report_id/proof",
+            get(get_proof_by_report)
+                .post(create_proof)
+                .delete(clear_proof)
+                .layer(DefaultBodyLimit::max(limits.max_upload_bytes))
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    rate_limit::uploads,
+                )),
+        ),
+        (
             "/users/:id/pfp",
-            get(get_profile_picture).put(update_profile_picture),
-        )
-        .route("/users/:id/password", put(update_password))
-        .route("/users/:id/reports", get(get_reports_by_owner))
-        .route("/users/:id/reports/access", get(get_reports_by_view_access))
-        .with_state(AppState::init()?);
-
-    Ok(router)
+            get(get_profile_picture)
+                .put(update_profile_picture)
+                .layer(DefaultBodyLimit::max(limits.max_upload_bytes))
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    rate_limit::uploads,
+                )),
+        ),
+    ]
 }
 
-fn load_dotenv() {
-    match dotenvy::dotenv() {
-        Ok(_) => log::info!("Loaded info from dotenv"),
-        Err(err) => log::error!("Unable to load info from dotenv: \"{}\"", err),
-    }
+fn route_all(routes: Routes) -> Router<AppState> {
+    routes
+        .into_iter()
+        .fold(Router::new(), |router, (path, handler)| {
+            router.route(path, handler)
+        })
+}
+
+fn api(state: AppState, limits: &LimitsConfig) -> Router {
+    let uploads =
+        route_all(upload_routes(&state, limits)).route_layer(axum::middleware::from_fn_with_state(
+            (state.clone(), limits.max_upload_bytes),
+            idempotency::idempotency,
+        ));
+
+    route_all(routes(&state))
+        .route_layer(axum::middleware::from_fn_with_state(
+            (state.clone(), limits.max_body_bytes),
+            idempotency::idempotency,
+        ))
+        .merge(uploads)
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track,
+        ))
+        .route_layer(axum::middleware::from_fn(telemetry::trace))
+        .layer(DefaultBodyLimit::max(limits.max_body_bytes))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::requests,
+        ))
+        .with_state(state)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    logger::setup()?;
-    load_dotenv();
-
-    let app = Router::new().nest("/api", api()?);
-    let addr = std::net::SocketAddr::from((LOCALHOST, PORT));
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?;
-    log::info!("Server running on port {}", PORT);
+    let dotenv = dotenvy::dotenv();
+    let config = Config::load()?;
+    logger::setup(&config.log)?;
+    let tracer_provider = expenser::telemetry::init(&config.tracing)?;
+    match dotenv {
+        Ok(_) => log::info!("Loaded info from dotenv"),
+        Err(err) => log::error!("Unable to load info from dotenv: \"{}\"", err),
+    }
+
+    let (trigger, shutdown) = shutdown::channel();
+    let state = AppState::init(&config, shutdown.clone())?;
+    let mut workers = vec![tokio::spawn(events::run(
+        state.report_events().clone(),
+        config.database.url.clone(),
+        shutdown,
+    ))];
+    match notifications::Mailer::new(&config.smtp)? {
+        Some(mailer) => {
+            workers.push(tokio::spawn(notifications::run(state.clone(), mailer)));
+            log::info!("Started notification worker");
+        }
+        None => log::warn!("smtp.host not set, notification emails will stay queued"),
+    }
+    workers.push(tokio::spawn(webhooks::run(
+        state.clone(),
+        webhooks::client()?,
+    )));
+
+    if let Some(metrics_bind) = config.metrics.bind {
+        let interval = Duration::from_secs(config.metrics.refresh_interval_secs);
+        workers.push(tokio::spawn(metrics::refresh(state.clone(), interval)));
+        tokio::spawn(metrics::serve(metrics_bind, state.clone()));
+    }
+
+    let mut app = Router::new();
+    for tree in api_version::trees(&config.api) {
+        let prefix = tree.prefix.clone();
+        app = app.nest(&prefix, tree.layer(api(state.clone(), &config.limits)));
+    }
+    app = app.merge(openapi::docs());
+    if config.security.csrf_protection {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            security::Csrf::new(&config.cors),
+            security::protect,
+        ));
+    }
+    app = app.layer(axum::middleware::from_fn(request_id::propagate));
+    if let Some(cors) = config.cors.layer()? {
+        app = app.layer(cors);
+    }
+    for (name, value) in config.security.headers(config.tls.paths().is_some())? {
+        app = app.layer(SetResponseHeaderLayer::if_not_present(name, value));
+    }
+
+    let timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    let handle = axum_server::Handle::new();
+    tokio::spawn(shutdown::on_signal(trigger, handle.clone(), timeout));
+
+    let addr = config.server.bind;
+    match config.tls.paths() {
+        Some((cert, key)) => {
+            let tls = tls::load(cert, key).await?;
+            let interval = Duration::from_secs(config.tls.reload_interval_secs);
+            tokio::spawn(tls::reload(
+                tls.clone(),
+                cert.clone(),
+                key.clone(),
+                interval,
+            ));
+            if let Some(redirect_bind) = config.tls.redirect_bind {
+                tokio::spawn(tls::redirect(redirect_bind, addr.port()));
+            }
+
+            log::info!("Server listening on https://{addr}");
+            axum_server::bind_rustls(addr, tls)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+        None => {
+            log::info!("Server listening on http://{addr}");
+            axum_server::bind(addr)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+    }
+    log::info!("Stopped accepting requests, waiting for background workers");
+    shutdown::drain(workers, timeout).await;
+    if let Some(provider) = tracer_provider {
+        tokio::task::spawn_blocking(move || expenser::telemetry::flush(&provider)).await?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,80 @@
+use axum_server::Handle;
+use futures_util::future::join_all;
+use std::time::Duration;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+    task::JoinHandle,
+};
+
+/// Resolves once shutdown has started, cloned into everything that needs to stop
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+/// Starts shutdown for every [`Shutdown`] made with it
+pub struct Trigger(watch::Sender<bool>);
+
+pub fn channel() -> (Trigger, Shutdown) {
+    let (sender, receiver) = watch::channel(false);
+
+    (Trigger(sender), Shutdown(receiver))
+}
+
+impl Shutdown {
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Wait for shutdown to start
+    pub async fn triggered(mut self) {
+        while !*self.0.borrow_and_update() {
+            if self.0.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Wait for SIGINT or SIGTERM, then stop accepting connections and give open requests
+/// `timeout` to finish
+///
+/// A signal which cannot be listened for is only logged. The trigger is kept either way,
+/// since dropping it would shut everything down at once.
+pub async fn on_signal(trigger: Trigger, handle: Handle, timeout: Duration) {
+    let interrupt = async {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => log::info!("Received SIGINT, shutting down"),
+            Err(e) => {
+                log::error!("Unable to listen for SIGINT: {e}");
+                std::future::pending().await
+            }
+        }
+    };
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+                log::info!("Received SIGTERM, shutting down");
+            }
+            Err(e) => {
+                log::error!("Unable to listen for SIGTERM: {e}");
+                std::future::pending().await
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+    trigger.0.send_replace(true);
+    handle.graceful_shutdown(Some(timeout));
+}
+
+/// Wait up to `timeout` for background workers to finish what they are doing
+pub async fn drain(workers: Vec<JoinHandle<()>>, timeout: Duration) {
+    match tokio::time::timeout(timeout, join_all(workers)).await {
+        Ok(_) => log::info!("Background workers stopped"),
+        Err(_) => log::warn!("Background workers did not stop within {timeout:?}, abandoning them"),
+    }
+}
@@ -1,34 +1,121 @@
 use anyhow::Result;
+use axum::http::StatusCode;
 use diesel::{
     r2d2::{ConnectionManager, Pool, PooledConnection},
     PgConnection,
 };
 use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use crate::config::Config;
+use crate::events::{self, ReportEvent};
+use crate::metrics::Metrics;
+use crate::rate_limit::RateLimits;
+use crate::request_id;
+use crate::shutdown::Shutdown;
 
 #[derive(Clone)]
 #[allow(dead_code)]
 pub struct AppState {
     connection_pool: Arc<Pool<ConnectionManager<PgConnection>>>,
+    report_events: broadcast::Sender<ReportEvent>,
+    idempotency_window: chrono::Duration,
+    shutdown: Shutdown,
+    metrics: Arc<Metrics>,
+    rate_limits: Option<Arc<RateLimits>>,
 }
 
 impl AppState {
-    pub fn init() -> Result<Self> {
+    pub fn init(config: &Config, shutdown: Shutdown) -> Result<Self> {
         let state = Self {
-            connection_pool: Arc::new(expenser::database::init()?),
+            connection_pool: Arc::new(expenser::database::init(&config.database)?),
+            report_events: broadcast::channel(events::CAPACITY).0,
+            idempotency_window: chrono::Duration::hours(config.server.idempotency_window_hours),
+            shutdown,
+            metrics: Arc::new(Metrics::new()?),
+            rate_limits: RateLimits::new(&config.rate_limit).map(Arc::new),
         };
         log::info!("Created new state object");
 
         Ok(state)
     }
 
-    pub fn get_conn(
+    /// State whose pool never connects, for exercising routing without a database
+    #[cfg(test)]
+    pub fn detached() -> Self {
+        Self {
+            connection_pool: Arc::new(Pool::builder().build_unchecked(ConnectionManager::new(""))),
+            report_events: broadcast::channel(events::CAPACITY).0,
+            idempotency_window: chrono::Duration::hours(1),
+            shutdown: crate::shutdown::channel().1,
+            metrics: Arc::new(Metrics::new().unwrap()),
+            rate_limits: None,
+        }
+    }
+
+    pub fn pool(&self) -> &Pool<ConnectionManager<PgConnection>> {
+        &self.connection_pool
+    }
+
+    pub fn report_events(&self) -> &broadcast::Sender<ReportEvent> {
+        &self.report_events
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// `None` when rate limiting is disabled
+    pub fn rate_limits(&self) -> Option<&RateLimits> {
+        self.rate_limits.as_deref()
+    }
+
+    /// Resolves once the server starts shutting down
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// How long idempotency keys and their responses are kept
+    pub fn idempotency_window(&self) -> chrono::Duration {
+        self.idempotency_window
+    }
+
+    /// Check a connection out of the pool without blocking the async runtime
+    pub async fn get_conn(
         &self,
-    ) -> Result<PooledConnection<ConnectionManager<PgConnection>>, axum::http::StatusCode> {
-        match expenser::database::get_connection(&self.connection_pool) {
+    ) -> Result<PooledConnection<ConnectionManager<PgConnection>>, StatusCode> {
+        match expenser::database::acquire(&self.connection_pool).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                log::error!("{e}");
+                Err(StatusCode::GATEWAY_TIMEOUT)
+            }
+        }
+    }
+
+    /// Run synchronous diesel calls on tokio's blocking thread pool
+    ///
+    /// The work runs to completion even if the request is dropped part way through,
+    /// so a transaction is never left half applied. Logging from `f` carries the
+    /// request's id, and model spans are children of the request's span.
+    pub async fn run<F, T>(&self, f: F) -> Result<T, StatusCode>
+    where
+        F: FnOnce(&mut PgConnection) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut conn = self.get_conn().await?;
+        let request_id = request_id::current();
+        let span = tracing::Span::current();
+
+        match tokio::task::spawn_blocking(move || {
+            span.in_scope(|| request_id::scope_blocking(request_id, || f(&mut conn)))
+        })
+        .await
+        {
             Ok(res) => Ok(res),
             Err(e) => {
                 log::error!("{e}");
-                Err(axum::http::StatusCode::GATEWAY_TIMEOUT)
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
             }
         }
     }
@@ -0,0 +1,41 @@
+use crate::request_id;
+use axum::{extract::MatchedPath, http::Request, middleware::Next, response::Response};
+use opentelemetry::global;
+use opentelemetry_http::HeaderExtractor;
+use tracing::{field::Empty, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Wrap each request in a server span, continuing the caller's trace if it sent a
+/// `traceparent` header
+///
+/// Model calls made while handling the request appear as child spans.
+pub async fn trace<B>(route: MatchedPath, request: Request<B>, next: Next<B>) -> Response {
+    let method = request.method().clone();
+    let span = tracing::info_span!(
+        "request",
+        otel.name = format!("{method} {}", route.as_str()),
+        otel.kind = "server",
+        otel.status_code = Empty,
+        http.request.method = method.as_str(),
+        http.route = route.as_str(),
+        http.response.status_code = Empty,
+        request_id = Empty,
+    );
+    if let Some(id) = request_id::current() {
+        span.record("request_id", id);
+    }
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    span.set_parent(parent);
+
+    let response = next.run(request).instrument(span.clone()).await;
+
+    let status = response.status();
+    span.record("http.response.status_code", status.as_u16());
+    if status.is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+
+    response
+}
@@ -0,0 +1,127 @@
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::Host,
+    http::{uri::Authority, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::signal::unix::{signal, SignalKind};
+
+pub async fn load(cert: &Path, key: &Path) -> Result<RustlsConfig> {
+    let (cert_pem, key_pem) = read_pem(cert, key).await?;
+    let res = RustlsConfig::from_pem(cert_pem, key_pem)
+        .await
+        .with_context(|| {
+            format!(
+                "Unable to load TLS certificate {} and key {}",
+                cert.display(),
+                key.display()
+            )
+        })?;
+    log::info!("Loaded TLS certificate {}", cert.display());
+
+    Ok(res)
+}
+
+/// Background worker reloading the certificate and key on SIGHUP, or when either file changes
+///
+/// Reloading only affects new handshakes, so open connections are kept. A certificate that
+/// fails to load is logged and the previous one stays in use.
+pub async fn reload(tls: RustlsConfig, cert: PathBuf, key: PathBuf, interval: Duration) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::error!("Unable to listen for SIGHUP, TLS certificates will not be reloaded: {e}");
+            return;
+        }
+    };
+    let mut last_modified = modified(&cert, &key);
+    // Ticks are only used when polling is enabled
+    let mut poll = tokio::time::interval(interval.max(Duration::from_secs(1)));
+    poll.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {
+                last_modified = modified(&cert, &key);
+                log::info!("Received SIGHUP, reloading TLS certificate");
+            }
+            _ = poll.tick(), if !interval.is_zero() => {
+                let current = modified(&cert, &key);
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+                log::info!("TLS certificate or key changed, reloading");
+            }
+        }
+
+        let res = match read_pem(&cert, &key).await {
+            Ok((cert_pem, key_pem)) => tls
+                .reload_from_pem(cert_pem, key_pem)
+                .await
+                .map_err(anyhow::Error::from),
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(()) => log::info!("Reloaded TLS certificate {}", cert.display()),
+            Err(e) => {
+                log::error!("Unable to reload TLS certificate, keeping the previous one: {e}")
+            }
+        }
+    }
+}
+
+/// Read the certificate chain and key, checking the chain is not empty
+///
+/// rustls accepts an empty chain, which would only show up as failing handshakes.
+async fn read_pem(cert: &Path, key: &Path) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cert_pem = tokio::fs::read(cert)
+        .await
+        .with_context(|| format!("Unable to read {}", cert.display()))?;
+    let key_pem = tokio::fs::read(key)
+        .await
+        .with_context(|| format!("Unable to read {}", key.display()))?;
+    if rustls_pemfile::certs(&mut cert_pem.as_slice())?.is_empty() {
+        bail!("{} contains no PEM certificates", cert.display());
+    }
+
+    Ok((cert_pem, key_pem))
+}
+
+/// Modification times of the certificate and key, following symlinks
+fn modified(cert: &Path, key: &Path) -> [Option<SystemTime>; 2] {
+    [cert, key].map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+}
+
+/// Plain HTTP server answering every request with a permanent redirect to HTTPS
+pub async fn redirect(addr: SocketAddr, https_port: u16) {
+    let app = Router::new().fallback(move |host: Host, uri: Uri| async move {
+        redirect_to_https(host, uri, https_port)
+    });
+
+    log::info!("Redirecting http://{addr} to HTTPS");
+    if let Err(e) = axum_server::bind(addr).serve(app.into_make_service()).await {
+        log::error!("HTTP redirect server failed: {e}");
+    }
+}
+
+fn redirect_to_https(Host(host): Host, uri: Uri, https_port: u16) -> Response {
+    let Ok(authority) = host.parse::<Authority>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+    let location = match https_port {
+        443 => format!("https://{}{path}", authority.host()),
+        port => format!("https://{}:{port}{path}", authority.host()),
+    };
+
+    Redirect::permanent(&location).into_response()
+}
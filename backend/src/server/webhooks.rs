@@ -0,0 +1,104 @@
+use crate::AppState;
+use anyhow::Result;
+use expenser::{Webhook, WebhookDelivery};
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BATCH_SIZE: i64 = 20;
+/// How long a claimed delivery is hidden from other workers while it is being sent
+const LEASE_SECONDS: f64 = 60.0;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const EVENT_HEADER: &str = "x-expenser-event";
+pub const DELIVERY_HEADER: &str = "x-expenser-delivery";
+/// `sha256=` followed by the hex encoded HMAC-SHA256 of the body, keyed with the webhook secret
+pub const SIGNATURE_HEADER: &str = "x-expenser-signature";
+
+pub fn client() -> Result<reqwest::Client> {
+    let res = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(concat!("expenser/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+
+    Ok(res)
+}
+
+/// Background worker sending queued webhook deliveries
+pub async fn run(state: AppState, client: reqwest::Client) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = state.shutdown().triggered() => break,
+        }
+        if let Err(e) = deliver_pending(&state, &client).await {
+            log::error!("{e}");
+        }
+    }
+    log::info!("Stopped webhook worker");
+}
+
+async fn deliver_pending(state: &AppState, client: &reqwest::Client) -> Result<()> {
+    let pending = expenser::database::run(state.pool(), |conn| {
+        WebhookDelivery::claim_pending(BATCH_SIZE, LEASE_SECONDS, conn)?
+            .into_iter()
+            .map(|delivery| Ok((Webhook::get_by_id(delivery.webhook_id, conn)?, delivery)))
+            .collect::<Result<Vec<_>>>()
+    })
+    .await??;
+
+    for (webhook, delivery) in pending {
+        let id = delivery.id;
+
+        match send(client, &webhook, &delivery).await {
+            Ok(status) if status.is_success() => {
+                expenser::database::run(state.pool(), move |conn| {
+                    delivery.mark_delivered(status.as_u16().into(), conn)
+                })
+                .await??;
+                log::info!("Delivered webhook delivery {id}");
+            }
+            Ok(status) => {
+                log::warn!("Webhook delivery {id} was rejected with {status}");
+                expenser::database::run(state.pool(), move |conn| {
+                    delivery.mark_failed(
+                        Some(status.as_u16().into()),
+                        &format!("Unexpected response status {status}"),
+                        conn,
+                    )
+                })
+                .await??;
+            }
+            Err(e) => {
+                log::warn!("Failed to send webhook delivery {id}: {e}");
+                expenser::database::run(state.pool(), move |conn| {
+                    delivery.mark_failed(None, &e.to_string(), conn)
+                })
+                .await??;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn send(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+) -> Result<reqwest::StatusCode> {
+    let body = serde_json::to_vec(&delivery.payload)?;
+
+    let res = client
+        .post(&webhook.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &delivery.event)
+        .header(DELIVERY_HEADER, delivery.id)
+        .header(SIGNATURE_HEADER, webhook.sign(&body))
+        .body(body)
+        .send()
+        .await?;
+
+    Ok(res.status())
+}
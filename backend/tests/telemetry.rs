@@ -0,0 +1,77 @@
+//! Spans recorded around model calls, captured in memory instead of sent to a collector
+//!
+//! Needs `DATABASE_URL` pointing at a migrated database.
+
+use expenser::{database, telemetry, telemetry::TracingConfig, Report};
+use futures_util::future::{self, BoxFuture};
+use opentelemetry::{trace::Status, Value};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use std::sync::{Arc, Mutex};
+use tracing::Instrument;
+
+#[derive(Debug, Clone, Default)]
+struct Captured(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for Captured {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        self.0.lock().unwrap().extend(batch);
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+fn attribute(span: &SpanData, key: &str) -> Option<Value> {
+    span.attributes
+        .iter()
+        .find(|attribute| attribute.key.as_str() == key)
+        .map(|attribute| attribute.value.clone())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn model_calls_are_traced() {
+    dotenvy::dotenv().ok();
+    let pool = match database::PoolConfig::from_env().and_then(|config| database::pool(&config)) {
+        Ok(pool) => pool,
+        Err(_) => {
+            eprintln!("Skipping telemetry test, DATABASE_URL is not set or reachable");
+            return;
+        }
+    };
+
+    let captured = Captured::default();
+    let provider = telemetry::provider(captured.clone(), &TracingConfig::default());
+    telemetry::install(&provider).expect("Unable to install subscriber");
+
+    let (count, missing) = database::run(&pool, |conn| {
+        (Report::count(conn), Report::get_by_id(-1, conn))
+    })
+    // Only the server and model targets are exported
+    .instrument(tracing::info_span!(target: "expenser", "test"))
+    .await
+    .expect("Unable to run queries");
+    let count = count.expect("Unable to count reports");
+    assert!(missing.is_err());
+
+    tokio::task::spawn_blocking(move || telemetry::flush(&provider))
+        .await
+        .unwrap();
+    let spans = captured.0.lock().unwrap();
+
+    let parent = spans
+        .iter()
+        .find(|span| span.name == "test")
+        .expect("Test span was not exported");
+    let count_span = spans
+        .iter()
+        .find(|span| span.name == "Report::count")
+        .expect("Report::count span was not exported");
+    assert_eq!(count_span.parent_span_id, parent.span_context.span_id());
+    assert_eq!(attribute(count_span, "db.rows"), Some(Value::I64(1)));
+    assert!(count >= 0);
+
+    let missing_span = spans
+        .iter()
+        .find(|span| span.name == "Report::get_by_id")
+        .expect("Report::get_by_id span was not exported");
+    assert_eq!(attribute(missing_span, "db.rows"), None);
+    assert!(matches!(missing_span.status, Status::Error { .. }));
+}